@@ -1,22 +1,25 @@
-use std::cell::Cell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fs::{remove_dir, DirBuilder};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::error::{ComRes, CommandError};
 use crate::job::Job;
 use crate::job::JobMap;
-use miette::{bail, Result};
+use crate::schedule::Schedule;
+use miette::{bail, miette, Report, Result};
 use miette::{Context, IntoDiagnostic};
 use serde::Deserialize;
 use serde::Deserializer;
 use serde::{de, Serialize};
-use time::format_description;
+use time::format_description::{self, OwnedFormatItem};
+use time::{OffsetDateTime, UtcOffset};
 
 #[derive(Debug, Deserialize, Default, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Conf {
     pub global: Global,
     /// All backup jobs
@@ -41,11 +44,36 @@ impl Conf {
 
         Ok((defaults, jobs))
     }
+
+    /// Verify global config and all jobs, without running restic.
+    ///
+    /// Returns all errors found instead of stopping at the first one.
+    pub fn validate(self) -> Vec<Report> {
+        let mut errors = Vec::new();
+        if let Err(e) = self.global.check() {
+            errors.push(e);
+        }
+        let defaults = Rc::new(self.global);
+        let mut names = HashSet::with_capacity(self.job.len());
+        for job_data in self.job.into_iter() {
+            if !names.insert(job_data.name.clone()) {
+                errors.push(miette!(
+                    "Multiple jobs with the same name '{}' detected!",
+                    job_data.name
+                ));
+            }
+            if let Err(e) = Job::new(job_data, defaults.clone()) {
+                errors.push(e);
+            }
+        }
+        errors
+    }
 }
 
 pub type Defaults = Rc<Global>;
 
 #[derive(Debug, Deserialize, Default, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Global {
     // Repository backends and defaults
     /// Rest backend defaults
@@ -57,28 +85,139 @@ pub struct Global {
     /// S3 backend defaults
     #[serde(alias = "S3")]
     pub s3: Option<S3Repository>,
+    /// Only warn about insecure permissions or ownership of the config file
+    #[serde(default)]
+    pub allow_insecure_config: bool,
+    /// Fail if the config file is accessible by its group, instead of only warning
+    #[serde(default)]
+    pub strict_permissions: bool,
+    /// Ignore unknown keys with a warning, instead of failing
+    #[serde(default)]
+    pub lenient_config: bool,
     /// Path to restic binary
     pub restic_binary: PathBuf,
     /// Verbose output, passed via CLI params.  
     /// Value [0-3] for disabled to maximum level.
-    #[serde(default)]
+    #[serde(default, skip_serializing)]
     pub verbose: usize,
+    /// Minimum verbosity of all jobs, passed via CLI params.
+    #[serde(skip)]
+    pub verbose_floor: usize,
     /// Default interval to use for backup jobs
     pub default_interval: u64,
+    /// Default restic pack size in MiB, `--pack-size`
+    pub pack_size: Option<u32>,
+    /// Default grouping of snapshots for retention and lookups, `--group-by`
+    pub group_by: Option<String>,
+    /// Additional arguments for every restic invocation, not verified
+    #[serde(default)]
+    pub restic_extra_args: Vec<String>,
+    /// Default retention of snapshots, applied after each successful backup
+    pub retention: Option<Retention>,
+    /// Maximum random delay in seconds added to the next run of each job.
+    /// Spreads out jobs that would otherwise start at the same time.
+    pub jitter: Option<u64>,
     /// Period of time to perform backup jobs
     pub period: Option<BackupTimeRange>,
+    /// Fixed UTC offset like `+02:00` for all local times, instead of the system timezone
+    #[serde(default, deserialize_with = "deserialize_offset")]
+    pub timezone: Option<UtcOffset>,
     /// Mysql Dump Path
     pub mysql_dump_binary: Option<PathBuf>,
+    /// Docker binary, for database dumps inside containers and docker volumes
+    pub docker_binary: Option<PathBuf>,
+    /// sqlite3 binary for consistent SQLite copies
+    pub sqlite_binary: Option<PathBuf>,
+    /// redis-cli binary for Redis backups
+    pub redis_cli_binary: Option<PathBuf>,
     /// Postgres Dump Path
     pub postgres_dump_binary: Option<PathBuf>,
+    /// Postgres Dumpall Path, for full cluster backups
+    pub postgres_dumpall_binary: Option<PathBuf>,
     /// Path for folder used for DB backups
     pub scratch_dir: PathBuf,
-    #[serde(default)]
-    pub verified_mysql_binary: Cell<bool>,
-    #[serde(default)]
-    pub verified_postgres_binary: Cell<bool>,
+    /// File the daemon writes the current time to on each scheduler iteration
+    pub heartbeat_file: Option<PathBuf>,
     #[serde(default = "default_true")]
     pub progress: bool,
+    /// Deprecated, `false` equals `progress_style = "plain"`
+    #[serde(default = "default_true", skip_serializing)]
+    pub progress_bar: bool,
+    /// How backup progress is rendered
+    #[serde(default)]
+    pub progress_style: ProgressStyle,
+    /// Minimum seconds between printed progress lines
+    #[serde(default = "default_progress_interval_secs")]
+    pub progress_interval_secs: u64,
+    /// Environment variables passed to restic and user commands
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Notifications on job results
+    #[serde(default)]
+    pub notify: NotifyConfig,
+    /// Prometheus node_exporter textfile to write job metrics to
+    pub metrics_textfile: Option<PathBuf>,
+    /// Directory for the backup history of each job, one JSON line per run
+    pub history_dir: Option<PathBuf>,
+    /// Number of runs kept in each history file, unlimited if not set
+    pub history_retention: Option<usize>,
+    /// Default healthcheck URL for jobs, `{job}` is replaced by the job name
+    pub healthcheck_url: Option<String>,
+    /// Skip directories containing a CACHEDIR.TAG, passes `--exclude-caches`
+    #[serde(default)]
+    pub exclude_caches: bool,
+    /// Excludes of all jobs, added before the excludes of each job
+    #[serde(default)]
+    pub default_excludes: Vec<String>,
+    /// Number of restic stderr lines included in errors
+    #[serde(default = "default_error_context_lines")]
+    pub error_context_lines: usize,
+    /// Remove stale repository locks and retry once, `restic unlock`
+    #[serde(default)]
+    pub auto_unlock: bool,
+    /// Minimum age in minutes of locks removed by `auto_unlock`
+    #[serde(default = "default_auto_unlock_age")]
+    pub auto_unlock_age: u64,
+    /// Disable notifications, passed via CLI params.
+    #[serde(skip)]
+    pub no_notify: bool,
+    /// Only print errors, passed via CLI params.
+    #[serde(skip)]
+    pub quiet: bool,
+    /// Minimum required restic version, startup fails for older versions
+    pub min_restic_version: Option<String>,
+    /// Detected restic version, unknown without running restic
+    #[serde(skip)]
+    pub restic_version: Option<ResticVersion>,
+    /// Prefix output lines with a timestamp, defaults to true for the daemon only
+    pub log_timestamps: Option<bool>,
+    /// Format of the timestamps, a time format description
+    pub log_timestamp_format: Option<String>,
+    /// Parsed `log_timestamp_format` if timestamps are enabled
+    #[serde(skip)]
+    pub log_timestamp: Option<OwnedFormatItem>,
+}
+
+/// Default of `log_timestamp_format`
+const DEFAULT_LOG_TIMESTAMP_FORMAT: &str = "[year]-[month]-[day] [hour]:[minute]:[second]";
+
+const fn default_progress_interval_secs() -> u64 {
+    1
+}
+
+/// Parse a time format description like `[hour]:[minute]`
+fn parse_timestamp_format(format: &str) -> Result<OwnedFormatItem> {
+    format_description::parse_owned::<2>(format)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Invalid config value 'log_timestamp_format' '{}'", format))
+}
+
+const fn default_error_context_lines() -> usize {
+    20
+}
+
+const fn default_auto_unlock_age() -> u64 {
+    60
 }
 
 const fn default_true() -> bool {
@@ -86,6 +225,7 @@ const fn default_true() -> bool {
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct BackupTimeRange {
     /// Backup time start
     #[serde(deserialize_with = "deserialize_time")]
@@ -95,6 +235,37 @@ pub struct BackupTimeRange {
     pub backup_end_time: time::Time,
 }
 
+impl BackupTimeRange {
+    /// Minutes per day outside of the period, in which no backups start
+    pub fn gap_minutes(&self) -> u64 {
+        let minutes = |t: time::Time| t.hour() as u64 * 60 + t.minute() as u64;
+        let (start, end) = (
+            minutes(self.backup_start_time),
+            minutes(self.backup_end_time),
+        );
+        match end > start {
+            // ex 06:00 - 18:00
+            true => 24 * 60 - (end - start),
+            // ex 22:00 - 02:00, equal times allow any time
+            false => start - end,
+        }
+    }
+}
+
+impl std::fmt::Display for BackupTimeRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (start, end) = (self.backup_start_time, self.backup_end_time);
+        write!(
+            f,
+            "{:02}:{:02} - {:02}:{:02}",
+            start.hour(),
+            start.minute(),
+            end.hour(),
+            end.minute()
+        )
+    }
+}
+
 impl Default for BackupTimeRange {
     fn default() -> Self {
         Self {
@@ -116,9 +287,137 @@ where
     time::Time::parse(&string, &time_fmt).map_err(de::Error::custom)
 }
 
+/// Deserialize a fixed UTC offset of `+HH:MM`, or `UTC`
+fn deserialize_offset<'de, D>(deserializer: D) -> Result<Option<UtcOffset>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let string: Option<String> = Deserialize::deserialize(deserializer)?;
+    string
+        .map(|v| parse_offset(&v))
+        .transpose()
+        .map_err(de::Error::custom)
+}
+
+fn parse_offset(value: &str) -> std::result::Result<UtcOffset, String> {
+    if value.eq_ignore_ascii_case("utc") || value == "Z" {
+        return Ok(UtcOffset::UTC);
+    }
+    let format =
+        format_description::parse_borrowed::<2>("[offset_hour sign:mandatory]:[offset_minute]")
+            .expect("invalid offset format");
+    UtcOffset::parse(value, &format)
+        .map_err(|e| format!("invalid timezone '{value}', expected an offset like '+02:00': {e}"))
+}
+
+/// Restic version of `major.minor.patch`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ResticVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl ResticVersion {
+    /// Compression, repository version 2 and `--pack-size`
+    pub const V0_14: Self = Self::new(0, 14, 0);
+    /// Negated exclude patterns
+    pub const V0_16: Self = Self::new(0, 16, 0);
+
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+}
+
+impl std::str::FromStr for ResticVersion {
+    type Err = String;
+
+    /// Parse `major.minor` with optional patch, suffixes like `-dev` are ignored
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("invalid version '{value}', expected for example '0.16.0'");
+        let mut parts = value.splitn(3, '.');
+        let mut number = |required: bool| -> std::result::Result<u32, String> {
+            match parts.next() {
+                Some(v) => {
+                    let digits: String = v.chars().take_while(|c| c.is_ascii_digit()).collect();
+                    digits.parse().map_err(|_| invalid())
+                }
+                None if required => Err(invalid()),
+                None => Ok(0),
+            }
+        };
+        Ok(Self::new(number(true)?, number(true)?, number(false)?))
+    }
+}
+
+impl std::fmt::Display for ResticVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
 impl Global {
+    /// Progress style, respecting the deprecated `progress_bar = false`
+    pub fn progress_style(&self) -> ProgressStyle {
+        match self.progress_bar {
+            true => self.progress_style,
+            false => ProgressStyle::Plain,
+        }
+    }
+
+    /// Current local time, in the configured timezone
+    ///
+    /// Falls back to UTC with a warning if the local offset can't be determined,
+    /// which happens in some containers and multi-threaded environments.
+    pub fn now(&self) -> OffsetDateTime {
+        if let Some(offset) = self.timezone {
+            return OffsetDateTime::now_utc().to_offset(offset);
+        }
+        OffsetDateTime::now_local().unwrap_or_else(|e| {
+            static WARNED: AtomicBool = AtomicBool::new(false);
+            if !WARNED.swap(true, Ordering::Relaxed) {
+                eprintln!(
+                    "Failed to determine the local timezone, using UTC. Set 'timezone' in global to pin it: {}",
+                    e
+                );
+            }
+            OffsetDateTime::now_utc()
+        })
+    }
+
+    /// Enable timestamps on output lines if configured, by default only for the daemon
+    pub fn init_log_timestamps(&mut self, daemon: bool) -> Result<()> {
+        if !self.log_timestamps.unwrap_or(daemon) {
+            return Ok(());
+        }
+        let format = self
+            .log_timestamp_format
+            .as_deref()
+            .unwrap_or(DEFAULT_LOG_TIMESTAMP_FORMAT);
+        self.log_timestamp = Some(parse_timestamp_format(format)?);
+        Ok(())
+    }
+
+    /// Timestamp prefix of output lines, empty if disabled
+    pub fn log_prefix(&self) -> String {
+        self.log_timestamp
+            .as_ref()
+            .and_then(|format| self.now().format(format).ok())
+            .map(|v| v + " ")
+            .unwrap_or_default()
+    }
+
     /// Verify basic validity
     pub fn check(&self) -> Result<()> {
+        if let Some(version) = &self.min_restic_version {
+            version
+                .parse::<ResticVersion>()
+                .map_err(|e| miette!("Invalid config value 'min_restic_version': {}", e))?;
+        }
         if !self.restic_binary.exists() {
             bail!("Path for config value 'restic_binary' not accessible or doesn't exist!");
         }
@@ -128,32 +427,47 @@ impl Global {
         if !self.scratch_dir.is_dir() {
             bail!("Path for config value 'scratch_dir' is not an existing folder!");
         }
-        // test we can write to scratch_dir
-        let scratch_test_dir = self.scratch_dir.join("testing");
-        if scratch_test_dir.exists() {
-            eprintln!(
-                "Path for testing scratch dir write perms exists!\nPath {:?}",
-                scratch_test_dir
+        // test we can write to scratch_dir, unique per process for concurrent runs
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |v| v.as_nanos());
+        let scratch_test_dir =
+            self.scratch_dir
+                .join(format!("testing_{}_{}", std::process::id(), nanos));
+        if let Err(e) = DirBuilder::new().create(&scratch_test_dir) {
+            bail!(
+                "Failed to create scratch_dir test folder at {:?}: {:?}",
+                scratch_test_dir,
+                e
             );
-        } else {
-            let mut builder = DirBuilder::new();
-            builder.recursive(true);
-            if let Err(e) = builder.create(&scratch_test_dir) {
-                bail!(
-                    "Failed to create scratch_dir test folder recursively at {:?}: {:?}",
-                    scratch_test_dir,
-                    e
-                );
+        }
+        if let Err(e) = remove_dir(&scratch_test_dir) {
+            bail!(
+                "Failed to delete scatch_dir test folder again at {:?}: {:?}",
+                scratch_test_dir,
+                e
+            );
+        }
+
+        if let Some(format) = &self.log_timestamp_format {
+            parse_timestamp_format(format)?;
+        }
+        if let Some(path) = &self.heartbeat_file {
+            if !path
+                .parent()
+                .is_some_and(|v| v.as_os_str().is_empty() || v.is_dir())
+            {
+                bail!("Folder of config value 'heartbeat_file' does not exist!");
             }
-            if let Err(e) = remove_dir(&scratch_test_dir) {
-                bail!(
-                    "Failed to delete scatch_dir test folder again at {:?}: {:?}",
-                    scratch_test_dir,
-                    e
-                );
+        }
+        if let Some(path) = &self.history_dir {
+            if !path.is_dir() {
+                bail!("Folder of config value 'history_dir' does not exist!");
             }
         }
-
+        if self.history_retention == Some(0) {
+            bail!("Config value 'history_retention' has to be at least 1!");
+        }
         if let Some(period) = &self.period {
             if period.backup_start_time == period.backup_end_time {
                 bail!("Backup period start and end time can't be the same!");
@@ -164,16 +478,46 @@ impl Global {
                 bail!("Path for config value 'mysql_dump_binary' is not an exsiting file!");
             }
         }
+        if let Some(path) = &self.docker_binary {
+            if !path.is_file() {
+                bail!("Path for config value 'docker_binary' is not an exsiting file!");
+            }
+        }
+        if let Some(path) = &self.sqlite_binary {
+            if !path.is_file() {
+                bail!("Path for config value 'sqlite_binary' is not an exsiting file!");
+            }
+        }
+        if let Some(group_by) = &self.group_by {
+            check_group_by(group_by).wrap_err("Invalid config value 'group_by'")?;
+        }
+        if let Some(path) = &self.redis_cli_binary {
+            if !path.is_file() {
+                bail!("Path for config value 'redis_cli_binary' is not an exsiting file!");
+            }
+        }
         if let Some(path) = &self.postgres_dump_binary {
             if !path.is_file() {
                 bail!("Path for config value 'postgres_dump_binary' is not an exsiting file!");
             }
         }
+        if let Some(path) = &self.postgres_dumpall_binary {
+            if !path.is_file() {
+                bail!("Path for config value 'postgres_dumpall_binary' is not an exsiting file!");
+            }
+        }
+        self.notify.check()?;
+        if let Some(retention) = &self.retention {
+            retention
+                .check()
+                .wrap_err("Invalid config for 'retention'")?;
+        }
         if let Some(RestRepository {
             rest_host: _,
             server_pubkey_file,
             rest_user: _,
             rest_password: _,
+            insecure_tls: _,
         }) = &self.rest
         {
             if let Some(pubkey_file) = server_pubkey_file {
@@ -201,10 +545,45 @@ impl Global {
             Command::new(cmd)
         }
     }
-    pub fn postgres_cmd_base(&self, sudo: bool) -> Result<Command> {
-        let binary = match &self.postgres_dump_binary {
-            Some(path) => path.as_os_str(),
-            None => {
+    pub fn docker_cmd_base(&self) -> Command {
+        match &self.docker_binary {
+            Some(path) => Command::new(path),
+            None => Command::new("docker"),
+        }
+    }
+    pub fn redis_cmd_base(&self) -> Command {
+        if let Some(path) = &self.redis_cli_binary {
+            Command::new(path)
+        } else {
+            #[cfg(target_os = "windows")]
+            let cmd = "redis-cli.exe";
+            #[cfg(not(target_os = "windows"))]
+            let cmd = "redis-cli";
+
+            Command::new(cmd)
+        }
+    }
+    pub fn sqlite_cmd_base(&self) -> Command {
+        if let Some(path) = &self.sqlite_binary {
+            Command::new(path)
+        } else {
+            #[cfg(target_os = "windows")]
+            let cmd = "sqlite3.exe";
+            #[cfg(not(target_os = "windows"))]
+            let cmd = "sqlite3";
+
+            Command::new(cmd)
+        }
+    }
+    /// pg_dump command, pg_dumpall for `dump_all`
+    pub fn postgres_cmd_base(&self, sudo: bool, dump_all: bool) -> Result<Command> {
+        let binary = match (
+            dump_all,
+            &self.postgres_dump_binary,
+            &self.postgres_dumpall_binary,
+        ) {
+            (false, Some(path), _) | (true, _, Some(path)) => path.as_os_str(),
+            (false, None, _) => {
                 #[cfg(target_os = "windows")]
                 {
                     OsStr::new("pg_dump.exe")
@@ -214,6 +593,16 @@ impl Global {
                     OsStr::new("pg_dump")
                 }
             }
+            (true, _, None) => {
+                #[cfg(target_os = "windows")]
+                {
+                    OsStr::new("pg_dumpall.exe")
+                }
+                #[cfg(not(target_os = "windows"))]
+                {
+                    OsStr::new("pg_dumpall")
+                }
+            }
         };
         match sudo {
             true => {
@@ -231,7 +620,365 @@ impl Global {
     }
 }
 
+/// Snapshots to keep, passed to `restic forget` after a successful backup
+#[derive(Debug, Deserialize, Default, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Retention {
+    pub keep_last: Option<u32>,
+    pub keep_hourly: Option<u32>,
+    pub keep_daily: Option<u32>,
+    pub keep_weekly: Option<u32>,
+    pub keep_monthly: Option<u32>,
+    pub keep_yearly: Option<u32>,
+    /// Keep all snapshots within this duration, `1y5m7d2h`
+    pub keep_within: Option<String>,
+    pub keep_within_hourly: Option<String>,
+    pub keep_within_daily: Option<String>,
+    pub keep_within_weekly: Option<String>,
+    pub keep_within_monthly: Option<String>,
+    pub keep_within_yearly: Option<String>,
+    /// Remove unreferenced data, `--prune`
+    #[serde(default)]
+    pub prune: bool,
+}
+
+impl Retention {
+    fn counts(&self) -> [(&'static str, Option<u32>); 6] {
+        [
+            ("keep-last", self.keep_last),
+            ("keep-hourly", self.keep_hourly),
+            ("keep-daily", self.keep_daily),
+            ("keep-weekly", self.keep_weekly),
+            ("keep-monthly", self.keep_monthly),
+            ("keep-yearly", self.keep_yearly),
+        ]
+    }
+
+    fn durations(&self) -> [(&'static str, Option<&str>); 6] {
+        [
+            ("keep-within", self.keep_within.as_deref()),
+            ("keep-within-hourly", self.keep_within_hourly.as_deref()),
+            ("keep-within-daily", self.keep_within_daily.as_deref()),
+            ("keep-within-weekly", self.keep_within_weekly.as_deref()),
+            ("keep-within-monthly", self.keep_within_monthly.as_deref()),
+            ("keep-within-yearly", self.keep_within_yearly.as_deref()),
+        ]
+    }
+
+    /// Verify basic validity
+    pub fn check(&self) -> Result<()> {
+        for (name, value) in self.durations() {
+            if let Some(value) = value {
+                if !is_restic_duration(value) {
+                    bail!(
+                        "Invalid duration '{}' for '{}', expected for example '30d' or '1y6m'!",
+                        value,
+                        name.replace('-', "_")
+                    );
+                }
+            }
+        }
+        let counts = self.counts().iter().any(|(_, v)| v.is_some());
+        let durations = self.durations().iter().any(|(_, v)| v.is_some());
+        if !counts && !durations {
+            bail!("Retention specified, but no 'keep_*' value is set!");
+        }
+        Ok(())
+    }
+
+    /// Arguments for `restic forget`
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        for (name, value) in self.counts() {
+            if let Some(value) = value {
+                args.push(format!("--{name}"));
+                args.push(value.to_string());
+            }
+        }
+        for (name, value) in self.durations() {
+            if let Some(value) = value {
+                args.push(format!("--{name}"));
+                args.push(value.to_owned());
+            }
+        }
+        if self.prune {
+            args.push(String::from("--prune"));
+        }
+        args
+    }
+}
+
+/// Restic duration, number and unit pairs of `y`, `m`, `d` and `h`, `1y5m7d2h`
+fn is_restic_duration(value: &str) -> bool {
+    parse_restic_duration(value).is_some()
+}
+
+/// Parse restic duration syntax, a month counts as 30 and a year as 365 days
+pub fn parse_restic_duration(value: &str) -> Option<time::Duration> {
+    if value.is_empty() {
+        return None;
+    }
+    let mut total = time::Duration::ZERO;
+    let mut number: Option<i64> = None;
+    for c in value.chars() {
+        let hours = match c {
+            '0'..='9' => {
+                let digit = c.to_digit(10).unwrap() as i64;
+                number = Some(number.unwrap_or(0).checked_mul(10)?.checked_add(digit)?);
+                continue;
+            }
+            'y' => 365 * 24,
+            'm' => 30 * 24,
+            'd' => 24,
+            'h' => 1,
+            _ => return None,
+        };
+        total = total.checked_add(time::Duration::hours(number.take()?.checked_mul(hours)?))?;
+    }
+    match number {
+        Some(_) => None,
+        None => Some(total),
+    }
+}
+
+/// Notification channels
+#[derive(Debug, Deserialize, Default, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyConfig {
+    /// Email notification of job results
+    pub email: Option<EmailNotify>,
+    /// HTTP POST of job results
+    pub webhook: Option<WebhookNotify>,
+    /// ntfy push notifications
+    pub ntfy: Option<NtfyNotify>,
+    /// Gotify push notifications
+    pub gotify: Option<GotifyNotify>,
+}
+
+impl NotifyConfig {
+    /// Verify basic validity
+    pub fn check(&self) -> Result<()> {
+        if let Some(email) = &self.email {
+            email
+                .check()
+                .wrap_err("Invalid config for 'notify.email'")?;
+        }
+        if let Some(webhook) = &self.webhook {
+            if webhook.url.is_empty() {
+                bail!("Config value 'notify.webhook.url' is empty!");
+            }
+        }
+        if let Some(ntfy) = &self.ntfy {
+            if ntfy.topic.is_empty() {
+                bail!("Config value 'notify.ntfy.topic' is empty!");
+            }
+            ntfy.priority
+                .check(1..=5)
+                .wrap_err("Invalid config for 'notify.ntfy.priority'")?;
+        }
+        if let Some(gotify) = &self.gotify {
+            if gotify.url.is_empty() || gotify.token.is_empty() {
+                bail!("Config values 'notify.gotify.url' and 'notify.gotify.token' are required!");
+            }
+            gotify
+                .priority
+                .check(0..=10)
+                .wrap_err("Invalid config for 'notify.gotify.priority'")?;
+        }
+        Ok(())
+    }
+
+    /// Whether the channel is configured
+    pub fn is_configured(&self, channel: NotifyChannel) -> bool {
+        match channel {
+            NotifyChannel::Email => self.email.is_some(),
+            NotifyChannel::Webhook => self.webhook.is_some(),
+            NotifyChannel::Ntfy => self.ntfy.is_some(),
+            NotifyChannel::Gotify => self.gotify.is_some(),
+        }
+    }
+}
+
+/// Notification channel, as named in `global.notify`
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyChannel {
+    Email,
+    Webhook,
+    Ntfy,
+    Gotify,
+}
+
+impl NotifyChannel {
+    pub fn name(self) -> &'static str {
+        match self {
+            NotifyChannel::Email => "email",
+            NotifyChannel::Webhook => "webhook",
+            NotifyChannel::Ntfy => "ntfy",
+            NotifyChannel::Gotify => "gotify",
+        }
+    }
+}
+
+/// Per job notification overrides
+#[derive(Debug, Deserialize, Default, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobNotify {
+    /// Channels to notify, defaults to all configured channels
+    pub channels: Option<Vec<NotifyChannel>>,
+    /// Events to notify on, overrides the value of each channel
+    pub on: Option<Vec<NotifyEvent>>,
+}
+
+/// Job outcome that triggers a notification
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyEvent {
+    Failure,
+    Success,
+}
+
+fn default_notify_on() -> Vec<NotifyEvent> {
+    vec![NotifyEvent::Failure]
+}
+
+const fn default_webhook_timeout() -> u64 {
+    10
+}
+
+/// Webhook settings, job results are POSTed as JSON
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookNotify {
+    /// Default URL, can be overridden per job
+    pub url: String,
+    /// Additional HTTP headers
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Events to notify on
+    #[serde(default = "default_notify_on")]
+    pub on: Vec<NotifyEvent>,
+    /// Request timeout in seconds
+    #[serde(default = "default_webhook_timeout")]
+    pub timeout: u64,
+}
+
+/// Message priority per event, server default if not set
+#[derive(Debug, Deserialize, Default, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PriorityMap {
+    pub failure: Option<u8>,
+    pub success: Option<u8>,
+}
+
+impl PriorityMap {
+    pub fn get(&self, event: NotifyEvent) -> Option<u8> {
+        match event {
+            NotifyEvent::Failure => self.failure,
+            NotifyEvent::Success => self.success,
+        }
+    }
+
+    fn check(&self, range: std::ops::RangeInclusive<u8>) -> Result<()> {
+        for priority in [self.failure, self.success].into_iter().flatten() {
+            if !range.contains(&priority) {
+                bail!(
+                    "Priority {} not in range {}-{}",
+                    priority,
+                    range.start(),
+                    range.end()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn default_ntfy_server() -> String {
+    String::from("https://ntfy.sh")
+}
+
+/// ntfy push notification settings
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NtfyNotify {
+    /// Server URL, defaults to `https://ntfy.sh`
+    #[serde(default = "default_ntfy_server")]
+    pub server: String,
+    pub topic: String,
+    /// Access token
+    pub token: Option<String>,
+    #[serde(default)]
+    pub priority: PriorityMap,
+    /// Events to notify on
+    #[serde(default = "default_notify_on")]
+    pub on: Vec<NotifyEvent>,
+}
+
+/// Gotify push notification settings
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GotifyNotify {
+    /// Server URL
+    pub url: String,
+    /// Application token
+    pub token: String,
+    #[serde(default)]
+    pub priority: PriorityMap,
+    /// Events to notify on
+    #[serde(default = "default_notify_on")]
+    pub on: Vec<NotifyEvent>,
+}
+
+/// SMTP settings for email notifications
 #[derive(Debug, Deserialize, Default, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmailNotify {
+    /// SMTP server host
+    pub smtp_host: String,
+    /// SMTP server port, defaults to 587 for STARTTLS and 465 otherwise
+    pub smtp_port: Option<u16>,
+    /// Use STARTTLS instead of implicit TLS
+    #[serde(default)]
+    pub starttls: bool,
+    /// Sender address, for example `backuprs <backup@example.com>`
+    pub from: String,
+    /// Recipient addresses
+    pub to: Vec<String>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    /// Events to notify on
+    #[serde(default = "default_notify_on")]
+    pub on: Vec<NotifyEvent>,
+}
+
+impl EmailNotify {
+    /// Verify basic validity
+    pub fn check(&self) -> Result<()> {
+        if self.smtp_host.is_empty() {
+            bail!("Config value 'smtp_host' is empty!");
+        }
+        if self.to.is_empty() {
+            bail!("No recipient specified in 'to'!");
+        }
+        self.from
+            .parse::<lettre::message::Mailbox>()
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Invalid sender address '{}'", self.from))?;
+        for to in self.to.iter() {
+            to.parse::<lettre::message::Mailbox>()
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Invalid recipient address '{}'", to))?;
+        }
+        if self.user.is_some() != self.password.is_some() {
+            bail!("Config values 'user' and 'password' have to be specified together!");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Serialize)]
+#[serde(deny_unknown_fields)]
 /// Defaults for rest backend
 pub struct RestRepository {
     /// Repostiroy host of the rest server. For example 10.0.0.1:443
@@ -241,6 +988,21 @@ pub struct RestRepository {
     pub server_pubkey_file: Option<PathBuf>,
     pub rest_user: Option<String>,
     pub rest_password: Option<String>,
+    /// Skip TLS certificate verification, `--insecure-tls`. Implies https.
+    pub insecure_tls: Option<bool>,
+}
+
+impl RestRepository {
+    pub fn insecure_tls(&self, defaults: &Option<RestRepository>) -> bool {
+        self.insecure_tls
+            .or(defaults.as_ref().and_then(|v| v.insecure_tls))
+            .unwrap_or(false)
+    }
+
+    /// Whether to connect via https
+    pub fn use_https(&self, defaults: &Option<RestRepository>) -> bool {
+        self.server_pubkey_file(defaults).is_some() || self.insecure_tls(defaults)
+    }
 }
 
 macro_rules! impl_required_getters {
@@ -273,6 +1035,7 @@ impl_required_getters!(RestRepository, rest_host);
 impl_optional_getters!(RestRepository, server_pubkey_file, Path);
 
 #[derive(Debug, Deserialize, Default, Serialize)]
+#[serde(deny_unknown_fields)]
 /// Defaults for S3 backend
 pub struct S3Repository {
     /// Host URL of the rest server.
@@ -287,6 +1050,7 @@ impl_required_getters!(S3Repository, aws_access_key_id);
 impl_required_getters!(S3Repository, aws_secret_access_key);
 
 #[derive(Debug, Deserialize, Default, Serialize)]
+#[serde(deny_unknown_fields)]
 /// Defaults for rest backend
 pub struct SftpRepository {
     /// Host URL of the sftp server.
@@ -302,16 +1066,49 @@ impl_required_getters!(SftpRepository, sftp_host);
 impl_optional_getters!(SftpRepository, sftp_command, str);
 impl_required_getters!(SftpRepository, sftp_user);
 
+/// Job of the config.
+///
+/// No `deny_unknown_fields`, serde doesn't support it with the flattened [JobBackend].
+/// Unknown keys are still rejected by the backend structs, which receive all remaining keys.
 #[derive(Debug, Deserialize, Default, Serialize)]
 pub struct JobData {
     /// For referencing jobs in commands and output
     pub name: String,
-    /// Command to run pre backup
-    pub pre_command: Option<CommandData>,
+    /// Disabled jobs are skipped when running all jobs and in daemon mode
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Commands to run pre backup, in order
+    #[serde(default, deserialize_with = "deserialize_commands")]
+    pub pre_command: Vec<CommandData>,
     /// Paths to include for backup
     pub paths: Vec<PathBuf>,
+    /// Timestamp of the next snapshot, `YYYY-MM-DD HH:MM:SS`, only set for one-off runs
+    #[serde(skip)]
+    pub snapshot_time: Option<String>,
+    /// Verbosity [0-3] of this job, overrides the global `verbose`
+    pub verbose: Option<usize>,
+    /// restic binary of this job, overrides the global `restic_binary`
+    pub restic_binary: Option<PathBuf>,
+    /// Detected version of the job `restic_binary`
+    #[serde(skip)]
+    pub restic_version: Option<ResticVersion>,
     /// Exclude items see [restic docs](https://restic.readthedocs.io/en/latest/040_backup.html#excluding-files)
     pub excludes: Vec<String>,
+    /// Whether to add the global `default_excludes`
+    #[serde(default = "default_true")]
+    pub inherit_excludes: bool,
+    /// Skip backup paths that don't exist, instead of failing the backup
+    #[serde(default)]
+    pub skip_missing_paths: bool,
+    /// Exclude files ignored by `.gitignore` files below the backup paths
+    #[serde(default)]
+    pub respect_gitignore: bool,
+    /// Skip directories containing a CACHEDIR.TAG, overrides the global value
+    pub exclude_caches: Option<bool>,
+    /// Remove stale repository locks and retry once, overrides the global value
+    pub auto_unlock: Option<bool>,
+    /// Minimum age in minutes of locks removed by `auto_unlock`, overrides the global value
+    pub auto_unlock_age: Option<u64>,
     /// Repository / Bucket
     pub repository: String,
     /// Job Backend data
@@ -319,34 +1116,552 @@ pub struct JobData {
     pub backend: JobBackend,
     /// Encryption key
     pub repository_key: String,
-    /// Command to run post backup
-    pub post_command: Option<CommandData>,
+    /// Additional repositories to backup to, after the primary one
+    #[serde(default)]
+    pub mirrors: Vec<Mirror>,
+    /// Abort on the first failed mirror, instead of trying all mirrors
+    #[serde(default)]
+    pub mirror_abort_on_error: bool,
+    /// Commands to run post backup, in order
+    #[serde(default, deserialize_with = "deserialize_commands")]
+    pub post_command: Vec<CommandData>,
     /// Whether to run the post_command even on backup failure
     #[serde(default)]
-    pub post_command_on_failure: Option<bool>,
+    pub post_command_on_failure: bool,
+    /// Skip the post_command if the backup added no new or changed files
+    #[serde(default)]
+    pub post_command_only_on_change: bool,
+    /// Command to run only if the backup or a pre-command failed, after the post commands
+    #[serde(default, deserialize_with = "deserialize_command")]
+    pub on_failure_command: Option<CommandData>,
     /// Interval in which to perform the backup
     pub interval: Option<u64>,
-    /// MySQL database name to backup
-    pub mysql_db: Option<String>,
+    /// Backup window of this job, overrides the global period
+    pub period: Option<BackupTimeRange>,
+    /// Days between repository checks via `restic check` in daemon mode, disabled if unset
+    pub check_interval_days: Option<u64>,
+    /// Restore this many random files of each new snapshot and compare their size
+    pub verify_restore: Option<usize>,
+    /// Schedule of `restic forget --prune` in daemon mode, instead of forgetting after each backup
+    pub prune_schedule: Option<Schedule>,
+    /// Secondary repository each new snapshot is copied to via `restic copy`
+    pub copy_to: Option<Mirror>,
+    /// MySQL database(s) to backup, with optional connection parameters
+    #[serde(default, deserialize_with = "deserialize_mysql_data")]
+    pub mysql_db: Option<MysqlData>,
     /// Postgres database name to backup
     pub postgres_db: Option<PostgresData>,
+    /// SQLite databases to backup via a consistent copy, excluded from `paths`
+    pub sqlite_db: Option<Vec<PathBuf>>,
+    /// Named docker volumes to backup, resolved to their mountpoint
+    #[serde(default)]
+    pub docker_volumes: Vec<String>,
+    /// Redis instance to backup
+    pub redis: Option<RedisData>,
+    /// Filesystem snapshot to backup from, instead of the live filesystem
+    pub fs_snapshot: Option<FsSnapshot>,
+    /// Environment variables passed to restic and user commands, overrides global values
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Restic pack size in MiB, `--pack-size`, overrides global value
+    pub pack_size: Option<u32>,
+    /// Repository format version for new repositories, `restic init --repository-version`
+    pub repo_version: Option<u8>,
+    /// Compression of new data, `--compression`, requires repository version 2
+    pub compression: Option<Compression>,
+    /// Grouping of snapshots for retention and lookups, `--group-by`, overrides global value
+    pub group_by: Option<String>,
+    /// Scheduling priority of restic and database dumps, -20 to 19, linux only
+    pub nice: Option<i32>,
+    /// Best-effort I/O priority of restic and database dumps, 0 (highest) to 7, linux only
+    pub ionice: Option<u8>,
+    /// Additional arguments for every restic invocation, appended to the global ones
+    #[serde(default)]
+    pub restic_extra_args: Vec<String>,
+    /// Retention of snapshots, replaces the global retention
+    pub retention: Option<Retention>,
+    /// Tags added to each snapshot, retention and snapshot lookups only consider snapshots with these
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Tags for selecting the snapshots retention applies to, instead of `tags`
+    pub forget_tags: Option<Vec<String>>,
+    /// Maximum age of the latest snapshot before `test` reports the job, `2d` or `36h`
+    pub max_age: Option<String>,
+    /// Override for the webhook notification URL
+    pub webhook_url: Option<String>,
+    /// Override of notification channels and events
+    pub notify: Option<JobNotify>,
+    /// Healthcheck URL to ping on start, success and failure
+    pub healthcheck_url: Option<String>,
+}
+
+/// Rendering of backup progress
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressStyle {
+    /// Periodic lines
+    Plain,
+    /// One in-place updated bar per job when attached to a terminal, plain lines otherwise
+    #[default]
+    Bar,
+}
+
+/// Restic compression mode, `--compression`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Auto,
+    Off,
+    Max,
+}
+
+impl Compression {
+    pub fn arg(self) -> &'static str {
+        match self {
+            Compression::Auto => "auto",
+            Compression::Off => "off",
+            Compression::Max => "max",
+        }
+    }
+}
+
+/// Additional repository of a job, a mirror or the target of `copy_to`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Mirror {
+    /// Repository / Bucket
+    pub repository: String,
+    /// Encryption key, defaults to the key of the job
+    pub repository_key: Option<String>,
+    /// Mirror Backend data
+    #[serde(flatten)]
+    pub backend: JobBackend,
 }
 
 /// Pre/Post user supplied command
 #[derive(Debug, Deserialize, Default, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct CommandData {
     pub command: String,
+    #[serde(default)]
     pub args: Vec<String>,
-    pub workdir: PathBuf,
+    /// Working directory, defaults to the one of backuprs
+    pub workdir: Option<PathBuf>,
+    /// Seconds after which the command is killed and the job fails
+    pub timeout: Option<u64>,
+    /// Last output lines kept for printing on failure, defaults to the global `error_context_lines`
+    pub output_lines: Option<usize>,
+    /// Run `command` via `sh -c` (`cmd /C` on windows), args are passed as positional parameters
+    #[serde(default)]
+    pub shell: bool,
+    /// Run as this user (name or uid), unix only
+    pub user: Option<String>,
+    /// Run with this group (name or gid), defaults to the group of `user`, unix only
+    pub group: Option<String>,
+}
+
+impl CommandData {
+    /// Working directory, unless empty
+    pub fn workdir(&self) -> Option<&Path> {
+        self.workdir
+            .as_deref()
+            .filter(|workdir| !workdir.as_os_str().is_empty())
+    }
+}
+
+/// MySQL backup data
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MysqlData {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    /// Passed via a temporary option file, never on the command line
+    pub password: Option<String>,
+    /// Unix socket, instead of host and port
+    pub socket: Option<PathBuf>,
+    /// Run mysqldump via `docker exec` inside this container
+    pub container: Option<String>,
+    #[serde(alias = "database")]
+    pub databases: MysqlDatabases,
+}
+
+/// Command as shell string or [CommandData] table
+///
+/// Deserialized via visitors instead of an untagged enum, to keep errors like unknown keys.
+enum CommandValue {
+    Shell(String),
+    Data(CommandData),
+}
+
+impl From<CommandValue> for CommandData {
+    fn from(value: CommandValue) -> Self {
+        match value {
+            CommandValue::Shell(command) => CommandData {
+                command,
+                shell: true,
+                ..Default::default()
+            },
+            CommandValue::Data(data) => data,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for CommandValue {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(CommandVisitor)
+    }
+}
+
+/// Visits a shell string or a [CommandData] table
+struct CommandVisitor;
+
+impl<'de> de::Visitor<'de> for CommandVisitor {
+    type Value = CommandValue;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a command string or table")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(CommandValue::Shell(v.to_owned()))
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        let data = CommandData::deserialize(de::value::MapAccessDeserializer::new(map))?;
+        Ok(CommandValue::Data(data))
+    }
+}
+
+/// Visits a single command or a list of them
+struct CommandsVisitor;
+
+impl<'de> de::Visitor<'de> for CommandsVisitor {
+    type Value = Vec<CommandValue>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a command string, a command table or a list of them")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        CommandVisitor.visit_str(v).map(|v| vec![v])
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        CommandVisitor.visit_map(map).map(|v| vec![v])
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut commands = Vec::new();
+        while let Some(command) = seq.next_element()? {
+            commands.push(command);
+        }
+        Ok(commands)
+    }
+}
+
+/// Accepts a single command or a list of them, each a shell string or a [CommandData] table
+fn deserialize_commands<'de, D>(deserializer: D) -> Result<Vec<CommandData>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let commands = deserializer.deserialize_any(CommandsVisitor)?;
+    Ok(commands.into_iter().map(Into::into).collect())
+}
+
+/// Accepts a shell string or a [CommandData] table
+fn deserialize_command<'de, D>(deserializer: D) -> Result<Option<CommandData>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<CommandValue>::deserialize(deserializer)?.map(Into::into))
+}
+
+/// Verify restic `--group-by` value, a comma separated list of `host`, `paths` and `tags`.
+///
+/// An empty value disables grouping.
+pub fn check_group_by(value: &str) -> Result<()> {
+    if value.is_empty() {
+        return Ok(());
+    }
+    let mut seen = Vec::with_capacity(3);
+    for key in value.split(',') {
+        if !["host", "paths", "tags"].contains(&key) {
+            bail!(
+                "Unknown key '{}' in '{}', allowed are host, paths and tags",
+                key,
+                value
+            );
+        }
+        if seen.contains(&key) {
+            bail!("Duplicate key '{}' in '{}'", key, value);
+        }
+        seen.push(key);
+    }
+    Ok(())
+}
+
+/// Plain database value (`mysql_db = "app"`) or a full [MysqlData] table
+///
+/// Deserialized via a visitor instead of an untagged enum, to keep errors like unknown keys.
+struct MysqlValue(MysqlData);
+
+impl<'de> Deserialize<'de> for MysqlValue {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(MysqlVisitor).map(MysqlValue)
+    }
+}
+
+/// Visits [MysqlDatabases] or a [MysqlData] table
+struct MysqlVisitor;
+
+impl MysqlVisitor {
+    fn databases(databases: MysqlDatabases) -> MysqlData {
+        MysqlData {
+            host: None,
+            port: None,
+            user: None,
+            password: None,
+            socket: None,
+            container: None,
+            databases,
+        }
+    }
 }
+
+impl<'de> de::Visitor<'de> for MysqlVisitor {
+    type Value = MysqlData;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a database name, a list of database names or a table")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        let databases = MysqlDatabases::deserialize(de::value::StrDeserializer::new(v))?;
+        Ok(Self::databases(databases))
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        let databases = MysqlDatabases::deserialize(de::value::SeqAccessDeserializer::new(seq))?;
+        Ok(Self::databases(databases))
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        MysqlData::deserialize(de::value::MapAccessDeserializer::new(map))
+    }
+}
+
+/// Accepts the plain database value (`mysql_db = "app"`) or a full [MysqlData] table
+fn deserialize_mysql_data<'de, D>(deserializer: D) -> Result<Option<MysqlData>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<MysqlValue>::deserialize(deserializer)?.map(|v| v.0))
+}
+
+/// MySQL databases to dump, a name, a list of names or `"*"` for all databases
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "MysqlDatabasesValue", into = "MysqlDatabasesValue")]
+pub enum MysqlDatabases {
+    All,
+    List(Vec<String>),
+}
+
+/// Config representation of [MysqlDatabases]
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum MysqlDatabasesValue {
+    Single(String),
+    List(Vec<String>),
+}
+
+impl TryFrom<MysqlDatabasesValue> for MysqlDatabases {
+    type Error = String;
+
+    fn try_from(value: MysqlDatabasesValue) -> std::result::Result<Self, Self::Error> {
+        let list = match value {
+            MysqlDatabasesValue::Single(v) if v == "*" => return Ok(MysqlDatabases::All),
+            MysqlDatabasesValue::Single(v) => vec![v],
+            MysqlDatabasesValue::List(v) => v,
+        };
+        if list.is_empty() {
+            return Err(String::from("mysql_db is an empty list"));
+        }
+        if let Some(name) = list.iter().find(|v| v.is_empty() || v.as_str() == "*") {
+            return Err(format!(
+                "invalid database name '{name}' in mysql_db list, use mysql_db = \"*\" for all databases"
+            ));
+        }
+        Ok(MysqlDatabases::List(list))
+    }
+}
+
+impl From<MysqlDatabases> for MysqlDatabasesValue {
+    fn from(value: MysqlDatabases) -> Self {
+        match value {
+            MysqlDatabases::All => MysqlDatabasesValue::Single(String::from("*")),
+            MysqlDatabases::List(v) => MysqlDatabasesValue::List(v),
+        }
+    }
+}
+
+/// Filesystem snapshot, created after the pre commands and removed after the backup
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FsSnapshot {
+    pub kind: FsSnapshotKind,
+    /// LVM `vg/lv`, ZFS dataset or btrfs subvolume path
+    pub volume: String,
+    /// Size of LVM snapshots, for example `5G`
+    pub size: Option<String>,
+    /// Where the volume is mounted, backup paths below are read from the snapshot instead
+    pub mountpoint: PathBuf,
+    /// Additional mount options for the snapshot, always mounted read-only
+    pub mount_options: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FsSnapshotKind {
+    Lvm,
+    Zfs,
+    Btrfs,
+}
+
+impl FsSnapshot {
+    pub fn check(&self, paths: &[PathBuf]) -> Result<()> {
+        if cfg!(not(target_os = "linux")) {
+            bail!("Filesystem snapshots are only supported on linux!");
+        }
+        if self.kind == FsSnapshotKind::Lvm {
+            if self.size.is_none() {
+                bail!("LVM snapshots require a 'size'!");
+            }
+            if !self.volume.contains('/') {
+                bail!(
+                    "LVM volume '{}' has to be of the form 'vg/lv'!",
+                    self.volume
+                );
+            }
+        }
+        if !self.mountpoint.is_absolute() {
+            bail!("'mountpoint' has to be an absolute path!");
+        }
+        if !paths.iter().any(|path| path.starts_with(&self.mountpoint)) {
+            bail!(
+                "None of the backup paths is below the snapshot mountpoint {}!",
+                self.mountpoint.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Redis backup data
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RedisData {
+    /// Defaults to localhost
+    pub host: Option<String>,
+    /// Defaults to 6379
+    pub port: Option<u16>,
+    /// Passed to redis-cli via environment
+    pub password: Option<String>,
+    /// Seconds to wait for BGSAVE to finish in rdb-copy mode
+    #[serde(default = "default_bgsave_timeout")]
+    pub bgsave_timeout: u64,
+    #[serde(flatten)]
+    pub mode: RedisMode,
+}
+
+fn default_bgsave_timeout() -> u64 {
+    300
+}
+
+/// How to create the Redis backup
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "mode", rename_all = "kebab-case")]
+pub enum RedisMode {
+    /// Trigger BGSAVE and backup the RDB file of the server
+    RdbCopy {
+        /// RDB file written by the server
+        rdb_path: PathBuf,
+    },
+    /// Fetch a RDB dump via `redis-cli --rdb` into the scratch dir
+    Dump,
+}
+
 /// Postgres backup data
 #[derive(Debug, Deserialize, Default, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct PostgresData {
     #[serde(default)]
     pub change_user: bool,
     pub password: Option<String>,
     pub user: Option<String>,
-    pub database: String,
+    /// Server host or unix socket directory, `PGHOST`
+    pub host: Option<String>,
+    /// Server port, `PGPORT`
+    pub port: Option<u16>,
+    /// Run the dump via `docker exec` inside this container, `change_user` runs it as postgres
+    pub container: Option<String>,
+    /// Database to dump via pg_dump
+    pub database: Option<String>,
+    /// Dump the whole cluster including roles and tablespaces via pg_dumpall,
+    /// instead of a single `database`
+    #[serde(default)]
+    pub dump_all: bool,
+    /// Dump format, defaults to plain SQL
+    pub format: Option<PostgresFormat>,
+    /// Additional pg_dump arguments, for example `--no-owner` or `--schema=public`
+    #[serde(default)]
+    pub extra_args: Vec<String>,
+}
+
+impl PostgresData {
+    /// Whether the host is remote, sudo to postgres only works for local peer authentication
+    pub fn is_remote(&self) -> bool {
+        match self.host.as_deref() {
+            None => false,
+            Some(host) => {
+                !(host.starts_with('/') || matches!(host, "" | "localhost" | "127.0.0.1" | "::1"))
+            }
+        }
+    }
+}
+
+/// pg_dump output format
+#[derive(Debug, Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PostgresFormat {
+    #[default]
+    Plain,
+    Custom,
+    Directory,
+}
+
+impl PostgresFormat {
+    /// `--format` argument of pg_dump
+    pub fn arg(self) -> &'static str {
+        match self {
+            PostgresFormat::Plain => "--format=p",
+            PostgresFormat::Custom => "--format=c",
+            PostgresFormat::Directory => "--format=d",
+        }
+    }
+
+    /// Name of the dump file, or directory for the directory format
+    pub fn dump_name(self) -> &'static str {
+        match self {
+            PostgresFormat::Plain => "db_dump_postgres.sql",
+            PostgresFormat::Custom => "db_dump_postgres.dump",
+            PostgresFormat::Directory => "db_dump_postgres",
+        }
+    }
 }
 
 /// Per job backend
@@ -390,6 +1705,187 @@ mod test {
         let _config: Conf = toml::from_str(config).unwrap();
     }
 
+    #[test]
+    fn test_retention() {
+        for valid in ["30d", "1y5m7d2h", "2h", "10y"] {
+            assert!(is_restic_duration(valid), "{valid}");
+        }
+        for invalid in ["", "d", "30", "30 d", "1w", "-1d", "d30"] {
+            assert!(!is_restic_duration(invalid), "{invalid}");
+        }
+        assert_eq!(
+            parse_restic_duration("1m2d3h"),
+            Some(time::Duration::hours(32 * 24 + 3))
+        );
+        let retention = Retention {
+            keep_daily: Some(7),
+            keep_within: Some(String::from("30d")),
+            prune: true,
+            ..Default::default()
+        };
+        retention.check().unwrap();
+        assert_eq!(
+            retention.args(),
+            ["--keep-daily", "7", "--keep-within", "30d", "--prune"]
+        );
+        assert!(Retention::default().check().is_err());
+    }
+
+    #[test]
+    fn test_postgres_format() {
+        let data: PostgresData =
+            toml::from_str("database = 'db'\nformat = 'custom'\nextra_args = ['--no-owner']")
+                .unwrap();
+        assert_eq!(data.database.as_deref(), Some("db"));
+        assert_eq!(data.format, Some(PostgresFormat::Custom));
+        assert_eq!(data.extra_args, ["--no-owner"]);
+        assert!(toml::from_str::<PostgresData>("database = 'db'\nformat = 'tar'").is_err());
+    }
+
+    #[test]
+    fn test_redis_mode() {
+        let data: RedisData =
+            toml::from_str("mode = 'rdb-copy'\nrdb_path = '/var/lib/redis/dump.rdb'").unwrap();
+        assert_eq!(
+            data.mode,
+            RedisMode::RdbCopy {
+                rdb_path: PathBuf::from("/var/lib/redis/dump.rdb")
+            }
+        );
+        assert_eq!(data.bgsave_timeout, 300);
+        let data: RedisData = toml::from_str("mode = 'dump'\nport = 6380").unwrap();
+        assert_eq!(data.mode, RedisMode::Dump);
+        assert_eq!(data.port, Some(6380));
+        assert!(toml::from_str::<RedisData>("mode = 'rdb-copy'").is_err());
+    }
+
+    #[test]
+    fn test_commands() {
+        #[derive(Deserialize)]
+        struct Value {
+            #[serde(default, deserialize_with = "deserialize_commands")]
+            pre_command: Vec<CommandData>,
+        }
+        let parse = |v: &str| toml::from_str::<Value>(v).map(|v| v.pre_command);
+        let single = parse("pre_command = { command = 'a', args = [], workdir = '/' }").unwrap();
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0].command, "a");
+        let list = parse(
+            "pre_command = [{ command = 'a', args = [], workdir = '/' }, { command = 'b', args = ['x'], workdir = '/', timeout = 5 }]",
+        )
+        .unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[1].command, "b");
+        assert_eq!(list[1].timeout, Some(5));
+        assert!(parse("").unwrap().is_empty());
+        let shell = parse("pre_command = 'systemctl stop app && sync'").unwrap();
+        assert_eq!(shell[0].command, "systemctl stop app && sync");
+        assert!(shell[0].shell);
+        let mixed = parse("pre_command = ['sync', { command = 'b', args = ['x'] }]").unwrap();
+        assert!(mixed[0].shell);
+        assert!(!mixed[1].shell);
+    }
+
+    #[test]
+    fn test_parse_offset() {
+        assert_eq!(
+            parse_offset("+02:00"),
+            Ok(UtcOffset::from_hms(2, 0, 0).unwrap())
+        );
+        assert_eq!(
+            parse_offset("-05:30"),
+            Ok(UtcOffset::from_hms(-5, -30, 0).unwrap())
+        );
+        assert_eq!(parse_offset("UTC"), Ok(UtcOffset::UTC));
+        assert!(parse_offset("02:00").is_err());
+        assert!(parse_offset("Europe/Berlin").is_err());
+    }
+
+    #[test]
+    fn test_log_timestamps() {
+        let mut global = Global {
+            timezone: Some(UtcOffset::UTC),
+            ..Default::default()
+        };
+        global.init_log_timestamps(false).unwrap();
+        assert_eq!(global.log_prefix(), "");
+        global.init_log_timestamps(true).unwrap();
+        // `2023-03-01 12:00:00 `
+        assert_eq!(global.log_prefix().len(), 20);
+
+        global.log_timestamps = Some(true);
+        global.log_timestamp_format = Some(String::from("[hour]:[minute]"));
+        global.init_log_timestamps(false).unwrap();
+        assert_eq!(global.log_prefix().len(), 6);
+        assert!(parse_timestamp_format("[hour]:[minutes]").is_err());
+    }
+
+    #[test]
+    fn test_restic_version() {
+        assert_eq!(
+            "0.14.0-dev".parse::<ResticVersion>(),
+            Ok(ResticVersion::new(0, 14, 0))
+        );
+        assert_eq!("0.16".parse(), Ok(ResticVersion::new(0, 16, 0)));
+        assert!("0.9.6".parse::<ResticVersion>().unwrap() < ResticVersion::V0_14);
+        assert!("1.0.0".parse::<ResticVersion>().unwrap() > ResticVersion::V0_14);
+        assert!("0".parse::<ResticVersion>().is_err());
+        assert!("latest".parse::<ResticVersion>().is_err());
+    }
+
+    #[test]
+    fn test_period_gap() {
+        let period = |start: &str, end: &str| {
+            toml::from_str::<BackupTimeRange>(&format!(
+                "backup_start_time = '{start}'\nbackup_end_time = '{end}'"
+            ))
+            .unwrap()
+        };
+        assert_eq!(period("06:00", "18:00").gap_minutes(), 12 * 60);
+        assert_eq!(period("22:00", "02:00").gap_minutes(), 20 * 60);
+        assert_eq!(period("01:30", "02:00").gap_minutes(), 23 * 60 + 30);
+        assert_eq!(period("00:00", "00:00").gap_minutes(), 0);
+    }
+
+    #[test]
+    fn test_group_by() {
+        check_group_by("").unwrap();
+        check_group_by("paths,tags").unwrap();
+        check_group_by("host,paths,tags").unwrap();
+        assert!(check_group_by("paths,paths").is_err());
+        assert!(check_group_by("path").is_err());
+        assert!(check_group_by("host, tags").is_err());
+    }
+
+    #[test]
+    fn test_mysql_db() {
+        #[derive(Deserialize)]
+        struct Value {
+            mysql_db: MysqlDatabases,
+        }
+        let parse = |v: &str| toml::from_str::<Value>(v).map(|v| v.mysql_db);
+        assert_eq!(
+            parse("mysql_db = 'app'").unwrap(),
+            MysqlDatabases::List(vec![String::from("app")])
+        );
+        assert_eq!(
+            parse("mysql_db = ['app', 'auth']").unwrap(),
+            MysqlDatabases::List(vec![String::from("app"), String::from("auth")])
+        );
+        assert_eq!(parse("mysql_db = '*'").unwrap(), MysqlDatabases::All);
+        assert!(parse("mysql_db = []").is_err());
+        assert!(parse("mysql_db = ['app', '*']").is_err());
+
+        let job: JobData = toml::from_str(
+            "name = 'job'\npaths = []\nexcludes = []\nrepository = ''\nrepository_key = ''\njob_type = 'S3'\nmysql_db = { host = 'db', port = 3307, password = 'secret', databases = '*' }",
+        )
+        .unwrap();
+        let mysql = job.mysql_db.unwrap();
+        assert_eq!(mysql.host.as_deref(), Some("db"));
+        assert_eq!(mysql.port, Some(3307));
+        assert_eq!(mysql.databases, MysqlDatabases::All);
+    }
+
     #[test]
     #[ignore]
     fn test_default_config_verify() {
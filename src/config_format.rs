@@ -0,0 +1,504 @@
+//! Parsing and generation of the config file in TOML, YAML or JSON, with diagnostics for unknown keys.
+use std::collections::BTreeSet;
+use std::ops::Range;
+use std::path::Path;
+
+use miette::{bail, Diagnostic, IntoDiagnostic, NamedSource, Result, SourceSpan};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::config::{
+    CommandData, Conf, Global, JobData, RestRepository, S3Repository, SftpRepository,
+};
+
+/// Supported config file formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Format by file extension, TOML for unknown ones
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|v| v.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("yaml") || ext.eq_ignore_ascii_case("yml") => {
+                Self::Yaml
+            }
+            Some(ext) if ext.eq_ignore_ascii_case("json") => Self::Json,
+            _ => Self::Toml,
+        }
+    }
+}
+
+const LENIENT_HINT: &str = "Set `lenient_config = true` in [global] to ignore unknown keys.";
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("Unknown config key `{key}`")]
+#[diagnostic(code(config::unknown_key))]
+pub struct UnknownKey {
+    key: String,
+    #[source_code]
+    src: NamedSource,
+    #[label("unknown key")]
+    span: SourceSpan,
+    #[help]
+    help: String,
+}
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("{message}")]
+#[diagnostic(code(config::invalid))]
+pub struct InvalidConfig {
+    message: String,
+    #[source_code]
+    src: NamedSource,
+    #[label("here")]
+    span: Option<SourceSpan>,
+    #[help]
+    help: Option<String>,
+}
+
+/// Parse the config in the given format.
+///
+/// Returns the config and a warning for each key ignored due to `lenient_config`.
+pub fn parse(format: ConfigFormat, name: &str, source: &str) -> Result<(Conf, Vec<String>)> {
+    let (result, location) = match format {
+        ConfigFormat::Toml => return parse_toml(name, source),
+        ConfigFormat::Yaml => match serde_yaml::from_str(source) {
+            Ok(config) => return reject_lenient(config),
+            Err(e) => {
+                let location = e.location().map(|v| v.index());
+                (e.to_string(), location)
+            }
+        },
+        ConfigFormat::Json => match serde_json::from_str(source) {
+            Ok(config) => return reject_lenient(config),
+            Err(e) => {
+                let location = line_offset(source, e.line(), e.column());
+                (e.to_string(), location)
+            }
+        },
+    };
+    let help =
+        unknown_field(&result).map(|unknown| match suggest(&unknown.key, &unknown.expected) {
+            Some(field) => format!("Did you mean `{field}`?"),
+            None => String::from("Unknown keys are only ignored via lenient_config for TOML."),
+        });
+    Err(InvalidConfig {
+        message: result,
+        src: NamedSource::new(name, source.to_owned()),
+        span: location.map(|v| v.into()),
+        help,
+    }
+    .into())
+}
+
+/// Unknown keys are only skipped in TOML configs, fail instead of silently ignoring the setting
+fn reject_lenient(config: Conf) -> Result<(Conf, Vec<String>)> {
+    if config.global.lenient_config {
+        bail!("`lenient_config` is only supported for TOML configs, unknown keys in YAML and JSON are always an error");
+    }
+    Ok((config, Vec::new()))
+}
+
+/// Byte offset of a 1-based line and column, None for line 0
+fn line_offset(source: &str, line: usize, column: usize) -> Option<usize> {
+    let start: usize = source
+        .split_inclusive('\n')
+        .take(line.checked_sub(1)?)
+        .map(|v| v.len())
+        .sum();
+    Some((start + column.saturating_sub(1)).min(source.len()))
+}
+
+/// Documented example config, optional keys are commented out
+const EXAMPLE: &str = include_str!("../config.toml.example");
+
+/// Example config in the given format, with all available keys.
+///
+/// YAML and JSON can't contain the comments of the TOML example,
+/// optional keys are listed as `null` there.
+pub fn generate(format: ConfigFormat) -> Result<String> {
+    // parsed to apply the serde defaults
+    let config = || toml::from_str::<Conf>(EXAMPLE).into_diagnostic();
+    match format {
+        ConfigFormat::Toml => Ok(EXAMPLE.to_owned()),
+        ConfigFormat::Yaml => serde_yaml::to_string(&config()?).into_diagnostic(),
+        ConfigFormat::Json => serde_json::to_string_pretty(&config()?).into_diagnostic(),
+    }
+}
+
+/// Parse a TOML config, unknown keys are an error unless `lenient_config` is set.
+fn parse_toml(name: &str, source: &str) -> Result<(Conf, Vec<String>)> {
+    let mut source = source.to_owned();
+    let mut warnings = Vec::new();
+    loop {
+        let err = match toml::from_str::<Conf>(&source) {
+            Ok(config) => return Ok((config, warnings)),
+            Err(e) => e,
+        };
+        let Some(unknown) = unknown_field(err.message()) else {
+            return Err(err).into_diagnostic();
+        };
+        let key_span = err
+            .span()
+            .and_then(|span| find_key(&source, span, &unknown.key));
+        if lenient(&source) {
+            if let Some(entry) = key_span
+                .as_ref()
+                .and_then(|v| entry_lines(&source, v.start))
+            {
+                let line = source[..entry.start].lines().count() + 1;
+                warnings.push(format!(
+                    "Ignoring unknown config key `{}` in line {}",
+                    unknown.key, line
+                ));
+                // keep the line numbers for later errors
+                let newlines = "\n".repeat(source[entry.clone()].matches('\n').count());
+                source.replace_range(entry, &newlines);
+                continue;
+            }
+        }
+        let span = key_span.or(err.span()).unwrap_or_default();
+        return Err(UnknownKey {
+            help: match suggest(&unknown.key, &unknown.expected) {
+                Some(field) => format!("Did you mean `{field}`? {LENIENT_HINT}"),
+                None => LENIENT_HINT.to_owned(),
+            },
+            key: unknown.key,
+            src: NamedSource::new(name, source),
+            span: span.into(),
+        }
+        .into());
+    }
+}
+
+struct UnknownField {
+    key: String,
+    /// Valid fields listed by serde
+    expected: Vec<String>,
+}
+
+/// Key and expected fields of an `unknown field` error message
+fn unknown_field(message: &str) -> Option<UnknownField> {
+    let message = message.split_once("unknown field `")?.1;
+    let (key, expected) = message.split_once('`')?;
+    Some(UnknownField {
+        key: key.to_owned(),
+        expected: expected
+            .split('`')
+            .skip(1)
+            .step_by(2)
+            .map(|v| v.to_owned())
+            .collect(),
+    })
+}
+
+/// Whether `lenient_config` is enabled in the global section
+fn lenient(source: &str) -> bool {
+    toml::from_str::<toml::Table>(source)
+        .ok()
+        .and_then(|v| v.get("global")?.get("lenient_config")?.as_bool())
+        .unwrap_or(false)
+}
+
+/// Position of `key` inside the error span, at the start of a line or as table header
+fn find_key(source: &str, span: Range<usize>, key: &str) -> Option<Range<usize>> {
+    // errors of regular tables point at the key, array tables and flattened ones at the table
+    if source.get(span.clone())?.trim_matches(['"', '\'']) == key {
+        return Some(span);
+    }
+    let mut offset = span.start;
+    for line in source.get(span)?.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        let start = offset + line.len() - trimmed.len();
+        offset += line.len();
+        let header = trimmed.trim_start_matches('[').trim_start();
+        let quoted = [format!("\"{key}\""), format!("'{key}'"), key.to_owned()];
+        for (rest, name) in [(trimmed, &quoted[..]), (header, &quoted[..])] {
+            for name in name {
+                let Some(after) = rest.strip_prefix(name.as_str()) else {
+                    continue;
+                };
+                let after = after.trim_start();
+                if after.starts_with(['=', '.', ']']) {
+                    let start = start + (trimmed.len() - rest.len());
+                    return Some(start..start + name.len());
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Byte range of the full entry starting in the line of `pos`, including multiline values.
+///
+/// Tables headers include all keys up to the next header.
+fn entry_lines(source: &str, pos: usize) -> Option<Range<usize>> {
+    let start = source[..pos].rfind('\n').map(|v| v + 1).unwrap_or(0);
+    let rest = &source[start..];
+    let mut end = start;
+    if rest.starts_with('[') {
+        for (i, line) in rest.split_inclusive('\n').enumerate() {
+            if i > 0 && line.trim_start().starts_with('[') {
+                break;
+            }
+            end += line.len();
+        }
+        return Some(start..end);
+    }
+    for line in rest.split_inclusive('\n') {
+        end += line.len();
+        if toml::from_str::<toml::Table>(&source[start..end]).is_ok() {
+            return Some(start..end);
+        }
+    }
+    None
+}
+
+/// Nearest known field name to `key`, if any is close enough
+fn suggest(key: &str, expected: &[String]) -> Option<String> {
+    let mut fields: BTreeSet<String> = expected.iter().cloned().collect();
+    fields.extend(known_fields());
+    fields
+        .into_iter()
+        .map(|field| (levenshtein(key, &field), field))
+        .filter(|(distance, _)| *distance <= (key.chars().count() / 3).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, field)| field)
+}
+
+/// Field names of all config sections
+fn known_fields() -> BTreeSet<String> {
+    fn keys(value: impl Serialize, fields: &mut BTreeSet<String>) {
+        if let Ok(serde_json::Value::Object(map)) = serde_json::to_value(value) {
+            fields.extend(map.into_iter().map(|(k, _)| k));
+        }
+    }
+    let mut fields = BTreeSet::new();
+    keys(Conf::default(), &mut fields);
+    keys(Global::default(), &mut fields);
+    keys(JobData::default(), &mut fields);
+    keys(RestRepository::default(), &mut fields);
+    keys(S3Repository::default(), &mut fields);
+    keys(SftpRepository::default(), &mut fields);
+    keys(CommandData::default(), &mut fields);
+    fields
+}
+
+/// Edit distance between `a` and `b`
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let cost = usize::from(a != *b);
+            let next = (row[j + 1] + 1).min(row[j] + 1).min(prev + cost);
+            prev = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::MysqlDatabases;
+
+    const CONFIG: &str = "[global]
+restic_binary = 'restic'
+default_interval = 60
+scratch_dir = 'scratch'
+
+[[job]]
+name = 'a'
+repository = 'repo'
+repository_key = 'key'
+job_type = 'S3'
+paths = ['/srv']
+excludes = []
+intervall = 30
+";
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("interval", "interval"), 0);
+        assert_eq!(levenshtein("intervall", "interval"), 1);
+        assert_eq!(levenshtein("pahts", "paths"), 2);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(suggest("intervall", &[]).unwrap(), "interval");
+        assert_eq!(suggest("xyz", &[]), None);
+    }
+
+    #[test]
+    fn test_unknown_key() {
+        let err = parse(ConfigFormat::Toml, "config.toml", CONFIG).unwrap_err();
+        let err = err.downcast_ref::<UnknownKey>().unwrap();
+        assert_eq!(err.key, "intervall");
+        assert_eq!(err.span.offset(), CONFIG.find("intervall").unwrap());
+        assert!(err.help.contains("`interval`"));
+
+        let config = CONFIG.replace(
+            "job_type = 'S3'",
+            "job_type = 'S3'\n[job.pre_command]\ncomand = 'sync'",
+        );
+        let err = parse(ConfigFormat::Toml, "config.toml", &config).unwrap_err();
+        assert_eq!(err.downcast_ref::<UnknownKey>().unwrap().key, "comand");
+
+        // nested tables of global and job
+        let config = CONFIG.replace("intervall", "interval");
+        let global = |table: &str| {
+            config.replace(
+                "scratch_dir = 'scratch'\n",
+                &format!("scratch_dir = 'scratch'\n{table}\n"),
+            )
+        };
+        let job = |table: &str| format!("{config}{table}\n");
+        for (source, key, field) in [
+            (
+                global("[global.retention]\nkeep_dayly = 7"),
+                "keep_dayly",
+                "keep_daily",
+            ),
+            (
+                global("[global.notify.email]\nsmtp_host = 'mail'\nsmtp_prot = 25"),
+                "smtp_prot",
+                "smtp_port",
+            ),
+            (
+                job("[job.postgres_db]\ndatabse = 'app'"),
+                "databse",
+                "database",
+            ),
+            (
+                job("[job.mysql_db]\ndatabases = 'app'\nsoket = '/run/mysqld.sock'"),
+                "soket",
+                "socket",
+            ),
+        ] {
+            let err = parse(ConfigFormat::Toml, "config.toml", &source).unwrap_err();
+            let err = err.downcast_ref::<UnknownKey>().unwrap();
+            assert_eq!(err.key, key);
+            assert!(err.help.contains(&format!("`{field}`")), "{}", err.help);
+        }
+        // plain database values are still accepted
+        for (value, databases) in [
+            ("'app'", MysqlDatabases::List(vec![String::from("app")])),
+            (
+                "['app', 'wiki']",
+                MysqlDatabases::List(vec![String::from("app"), String::from("wiki")]),
+            ),
+            ("'*'", MysqlDatabases::All),
+        ] {
+            let source = config.replace("[[job]]\n", &format!("[[job]]\nmysql_db = {value}\n"));
+            let (config, _) = parse(ConfigFormat::Toml, "config.toml", &source).unwrap();
+            assert_eq!(
+                config.job[0].mysql_db.as_ref().unwrap().databases,
+                databases
+            );
+        }
+    }
+
+    #[test]
+    fn test_formats() {
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("config.toml")),
+            ConfigFormat::Toml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("/etc/b.YML")),
+            ConfigFormat::Yaml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("b.yaml")),
+            ConfigFormat::Yaml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("b.json")),
+            ConfigFormat::Json
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("config")),
+            ConfigFormat::Toml
+        );
+
+        for format in [ConfigFormat::Toml, ConfigFormat::Yaml, ConfigFormat::Json] {
+            let source = generate(format).unwrap();
+            let (config, warnings) = parse(format, "config", &source).unwrap();
+            assert!(warnings.is_empty());
+            assert_eq!(config.job[0].name, "Job1");
+            assert!(config.global.progress);
+        }
+        // CLI only and deprecated values
+        let global = serde_json::to_value(Global::default()).unwrap();
+        assert!(global.get("verbose").is_none());
+        assert!(global.get("progress_bar").is_none());
+        let yaml = generate(ConfigFormat::Yaml).unwrap();
+        assert!(yaml.contains("\n  pack_size: null\n"));
+
+        let source = "global:\n  restic_binary: restic\n  intervall: 5\n";
+        let err = parse(ConfigFormat::Yaml, "config.yaml", source).unwrap_err();
+        let err = err.downcast_ref::<InvalidConfig>().unwrap();
+        assert!(err.message.contains("unknown field `intervall`"));
+        assert_eq!(
+            err.span.unwrap().offset(),
+            source.find("intervall").unwrap()
+        );
+        assert!(err.help.as_ref().unwrap().contains("`interval`"));
+
+        let source = "{\n  \"global\": {\n    \"restic_binary\": 5\n  }\n}";
+        let err = parse(ConfigFormat::Json, "config.json", source).unwrap_err();
+        let err = err.downcast_ref::<InvalidConfig>().unwrap();
+        assert!(err.message.contains("line 3"));
+        assert_eq!(&source[..err.span.unwrap().offset()].lines().count(), &3);
+    }
+
+    #[test]
+    fn test_generate_all_keys() {
+        fn keys(value: impl Serialize) -> Vec<String> {
+            match serde_json::to_value(value).unwrap() {
+                serde_json::Value::Object(map) => map.into_iter().map(|(k, _)| k).collect(),
+                _ => unreachable!(),
+            }
+        }
+        let example = generate(ConfigFormat::Toml).unwrap().to_lowercase();
+        let documented = |key: &str| {
+            example.lines().any(|line| {
+                let line = line.trim_start_matches(['#', ' ', '[']);
+                line.starts_with(&format!("{key} ="))
+                    || line.starts_with(&format!("global.{key}]"))
+                    || line.starts_with(&format!("job.{key}]"))
+            })
+        };
+        let mut missing: Vec<String> = keys(Global::default())
+            .into_iter()
+            .chain(keys(JobData::default()))
+            .filter(|key| !documented(key))
+            .collect();
+        missing.sort();
+        assert!(missing.is_empty(), "{missing:?}");
+    }
+
+    #[test]
+    fn test_lenient() {
+        let config = CONFIG.replace(
+            "scratch_dir = 'scratch'\n",
+            "scratch_dir = 'scratch'\nlenient_config = true\nextra = [\n  1,\n  2,\n]\n\
+            [global.custom]\nkey = 1\n\n[global.rest]\nrest_host = 'h'\n",
+        );
+        let (config, warnings) = parse(ConfigFormat::Toml, "config.toml", &config).unwrap();
+        assert_eq!(config.job[0].interval, None);
+        assert_eq!(config.global.rest.unwrap().rest_host.as_deref(), Some("h"));
+        assert_eq!(warnings.len(), 3);
+        assert!(warnings[0].contains("`extra` in line 6"));
+
+        let source = "global:\n  restic_binary: restic\n  default_interval: 60\n  scratch_dir: scratch\n  lenient_config: true\njob: []\n";
+        let err = parse(ConfigFormat::Yaml, "config.yaml", source).unwrap_err();
+        assert!(err.to_string().contains("only supported for TOML"));
+    }
+}
@@ -11,7 +11,7 @@ pub enum CommandError {
     #[diagnostic(code(restic::not_initialized))]
     NotInitialized,
 
-    #[error("Restic exited with errors {0}.")]
+    #[error("Restic exited with errors, {0}")]
     #[diagnostic(code(restic::error))]
     ResticError(String),
 
@@ -19,6 +19,31 @@ pub enum CommandError {
     #[diagnostic(code(restic::invalid_json))]
     InvalidResponse(#[from] serde_json::error::Error),
 
+    #[error("Backend unreachable or authentication failed: {0}")]
+    #[diagnostic(
+        code(restic::backend_unreachable),
+        help("Check the host and credentials of the repository backend.")
+    )]
+    BackendUnreachable(String),
+
+    #[error("Repository is already locked {0}")]
+    #[diagnostic(
+        code(restic::locked),
+        help("Remove stale locks via `restic unlock` or enable 'auto_unlock'.")
+    )]
+    RepositoryLocked(LockInfo),
+
+    #[error("Repository storage is full: {0}")]
+    #[diagnostic(
+        code(restic::repository_full),
+        help("Free up space on the backend, raise its quota or prune old snapshots.")
+    )]
+    RepositoryFull(String),
+
+    #[error("Command timed out after {0} seconds and was killed.")]
+    #[diagnostic(code(command::timeout))]
+    Timeout(u64),
+
     #[error("Missing required value for {0}, not specific in the defaults or job specific configuration.")]
     #[diagnostic(code(restic::invalid_config))]
     MissingConfigValue(&'static str),
@@ -31,3 +56,236 @@ impl PartialEq for CommandError {
 }
 
 pub type ComRes<T> = std::result::Result<T, CommandError>;
+
+/// Restic output (lowercase) of backends that can't be reached or refuse the credentials
+const UNREACHABLE_PATTERNS: &[&str] = &[
+    // network
+    "connection refused",
+    "connection reset",
+    "no such host",
+    "no route to host",
+    "network is unreachable",
+    "i/o timeout",
+    "context deadline exceeded",
+    "x509: ",
+    // authentication
+    "401 unauthorized",
+    "403 forbidden",
+    "accessdenied",
+    "access denied",
+    "invalidaccesskeyid",
+    "signaturedoesnotmatch",
+    "permission denied (publickey",
+    "ssh: handshake failed",
+    "unable to authenticate",
+];
+
+/// Restic output (lowercase) of backends without free space or exceeded quotas
+const FULL_PATTERNS: &[&str] = &[
+    // local and sftp
+    "no space left on device",
+    "disk quota exceeded",
+    // rest-server
+    "insufficient storage",
+    // S3 compatible and B2
+    "quotaexceeded",
+    "quota exceeded",
+    "xminiostoragefull",
+    "minimum free drive threshold",
+    "storage_cap_exceeded",
+    "storage cap exceeded",
+];
+
+/// First line of `output` containing one of the lowercase `patterns`
+fn find_line<'a>(output: &'a str, patterns: &[&str]) -> Option<&'a str> {
+    output.lines().find(|line| {
+        let line = line.to_lowercase();
+        patterns.iter().any(|p| line.contains(p))
+    })
+}
+
+impl CommandError {
+    /// Detect locked repositories and known backend failures in restic error output
+    pub fn from_restic_output(output: &str) -> Option<Self> {
+        if let Some(lock) = LockInfo::from_restic_output(output) {
+            return Some(CommandError::RepositoryLocked(lock));
+        }
+        if let Some(line) = find_line(output, FULL_PATTERNS) {
+            return Some(CommandError::RepositoryFull(line.trim().to_owned()));
+        }
+        find_line(output, UNREACHABLE_PATTERNS)
+            .map(|line| CommandError::BackendUnreachable(line.trim().to_owned()))
+    }
+
+    /// Whether the error or one of its causes is a full repository
+    pub fn is_repository_full(error: &miette::Report) -> bool {
+        error.chain().any(|e| {
+            matches!(
+                e.downcast_ref::<CommandError>(),
+                Some(CommandError::RepositoryFull(_))
+            )
+        })
+    }
+}
+
+/// Lock of a repository, as reported by restic
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LockInfo {
+    /// Process holding the lock
+    pub pid: Option<u32>,
+    /// Host of the locking process
+    pub host: Option<String>,
+    /// Age of the lock at the time of the error
+    pub age: Option<std::time::Duration>,
+}
+
+impl LockInfo {
+    /// Parse lock details from restic output, None if the repository isn't locked
+    ///
+    /// ```text
+    /// unable to create lock in backend: repository is already locked exclusively by PID 1234 on host by root (UID 0, GID 0)
+    /// lock was created at 2023-03-01 12:00:00 (1h2m3.5s ago)
+    /// ```
+    pub fn from_restic_output(output: &str) -> Option<Self> {
+        let locked = output
+            .lines()
+            .find(|line| line.contains("repository is already locked"))?;
+        let mut lock = LockInfo::default();
+        if let Some((_, holder)) = locked.split_once(" by PID ") {
+            let mut words = holder.split_whitespace();
+            lock.pid = words.next().and_then(|v| v.parse().ok());
+            if words.next() == Some("on") {
+                lock.host = words.next().map(str::to_owned);
+            }
+        }
+        lock.age = output
+            .lines()
+            .find(|line| line.contains("lock was created at"))
+            .and_then(|line| line.rsplit_once('('))
+            .and_then(|(_, age)| age.trim_end().strip_suffix(" ago)"))
+            .and_then(parse_go_duration);
+        Some(lock)
+    }
+}
+
+impl std::fmt::Display for LockInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.pid {
+            Some(pid) => write!(f, "by PID {pid}")?,
+            None => write!(f, "by an unknown process")?,
+        }
+        if let Some(host) = &self.host {
+            write!(f, " on {host}")?;
+        }
+        if let Some(age) = self.age {
+            write!(f, ", created {}s ago", age.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse a duration as printed by Go, `1h2m3.5s` or `250ms`
+fn parse_go_duration(value: &str) -> Option<std::time::Duration> {
+    let mut total = 0f64;
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(split);
+        let number: f64 = number.parse().ok()?;
+        let unit_len = tail
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+        let factor = match unit {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 1e-3,
+            "us" | "µs" => 1e-6,
+            "ns" => 1e-9,
+            _ => return None,
+        };
+        total += number * factor;
+        rest = tail;
+    }
+    Some(std::time::Duration::from_secs_f64(total))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_restic_output() {
+        let rest = "Fatal: unable to open config file: Stat: Head \"http://example.com:8000/repo/config\": dial tcp 10.0.0.1:8000: connect: connection refused\nIs there a repository at the following location?";
+        assert_eq!(
+            Some(CommandError::BackendUnreachable(String::new())),
+            CommandError::from_restic_output(rest)
+        );
+        let s3 = "Fatal: unable to open config file: Stat: The AWS Access Key Id you provided does not exist in our records. (InvalidAccessKeyId)";
+        assert_eq!(
+            Some(CommandError::BackendUnreachable(String::new())),
+            CommandError::from_restic_output(s3)
+        );
+        let sftp = "subprocess ssh: user@example.com: Permission denied (publickey,password).\nFatal: unable to open repository at sftp:user@example.com:/repo: unable to start the sftp session, error: EOF";
+        match CommandError::from_restic_output(sftp) {
+            Some(CommandError::BackendUnreachable(line)) => {
+                assert!(line.contains("Permission denied"))
+            }
+            v => panic!("unexpected {:?}", v),
+        }
+        let locked = "unable to create lock in backend: repository is already locked exclusively by PID 1234 on backup-host by root (UID 0, GID 0)\nlock was created at 2023-03-01 12:00:00 (1h2m3.5s ago)\nstorage ID 1a2b3c4d\nthe `unlock` command can be used to remove stale locks";
+        match CommandError::from_restic_output(locked) {
+            Some(CommandError::RepositoryLocked(lock)) => {
+                assert_eq!(lock.pid, Some(1234));
+                assert_eq!(lock.host.as_deref(), Some("backup-host"));
+                assert_eq!(lock.age.map(|v| v.as_secs()), Some(3723));
+            }
+            v => panic!("unexpected {:?}", v),
+        }
+        assert_eq!(parse_go_duration("250ms").map(|v| v.as_millis()), Some(250));
+        assert_eq!(
+            parse_go_duration("72h0m0s").map(|v| v.as_secs()),
+            Some(259200)
+        );
+        assert_eq!(parse_go_duration("5 minutes"), None);
+        let disk = "Save(<data/4d6f7b1a2c>) returned error, retrying after 552.330144ms: write /srv/repo/data/4d/4d6f7b1a2c: no space left on device\nFatal: unable to save snapshot: write /srv/repo/data/4d/4d6f7b1a2c: no space left on device";
+        match CommandError::from_restic_output(disk) {
+            Some(CommandError::RepositoryFull(line)) => {
+                assert!(line.starts_with("Save(<data/4d6f7b1a2c>)"))
+            }
+            v => panic!("unexpected {:?}", v),
+        }
+        let s3 = "Save(<data/4d6f7b1a2c>) returned error, retrying after 720.8ms: client.PutObject: Storage backend has reached its minimum free drive threshold. Please delete a few objects to proceed.";
+        assert_eq!(
+            Some(CommandError::RepositoryFull(String::new())),
+            CommandError::from_restic_output(s3)
+        );
+        let quota = "Fatal: unable to save snapshot: client.PutObject: The bucket quota was exceeded (QuotaExceeded)";
+        assert_eq!(
+            Some(CommandError::RepositoryFull(String::new())),
+            CommandError::from_restic_output(quota)
+        );
+        let rest = "Save(<data/4d6f7b1a2c>) returned error, retrying after 1.2s: unexpected HTTP response (507): 507 Insufficient Storage";
+        assert_eq!(
+            Some(CommandError::RepositoryFull(String::new())),
+            CommandError::from_restic_output(rest)
+        );
+        let report = miette::Report::new(CommandError::RepositoryFull(String::new()))
+            .wrap_err("Backup failed");
+        assert!(CommandError::is_repository_full(&report));
+        let report = miette::Report::new(CommandError::Timeout(1)).wrap_err("Backup failed");
+        assert!(!CommandError::is_repository_full(&report));
+        let not_initialized = "Fatal: unable to open config file: <config/> does not exist\nIs there a repository at the following location?";
+        assert_eq!(None, CommandError::from_restic_output(not_initialized));
+        assert_eq!(
+            None,
+            CommandError::from_restic_output("Fatal: wrong password or no key found")
+        );
+    }
+}
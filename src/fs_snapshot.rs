@@ -0,0 +1,288 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use miette::{bail, Context, IntoDiagnostic, Result};
+
+use crate::config::{FsSnapshot, FsSnapshotKind};
+
+/// Filesystem snapshot of a job, created before and removed after the backup.
+///
+/// Tracks every step that succeeded, so [ActiveSnapshot::teardown] also cleans up
+/// after partial failures, for example when mounting failed.
+pub struct ActiveSnapshot {
+    kind: FsSnapshotKind,
+    /// Origin volume: LVM `vg/lv`, ZFS dataset, btrfs subvolume path
+    volume: String,
+    /// LVM snapshot size
+    size: Option<String>,
+    mount_options: Option<String>,
+    /// Snapshot name, unique per job
+    name: String,
+    /// Directory the snapshot is mounted at
+    mount_dir: PathBuf,
+    created: bool,
+    mounted: bool,
+}
+
+impl ActiveSnapshot {
+    pub fn new(job_name: &str, config: &FsSnapshot, mount_dir: PathBuf) -> Self {
+        // only characters valid for all of LVM, ZFS and paths
+        let job_name: String = job_name
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() || c == '-' {
+                true => c,
+                false => '_',
+            })
+            .collect();
+        Self {
+            kind: config.kind,
+            volume: config.volume.clone(),
+            size: config.size.clone(),
+            mount_options: config.mount_options.clone(),
+            name: format!("backuprs_{job_name}"),
+            mount_dir,
+            created: false,
+            mounted: false,
+        }
+    }
+
+    /// Directory the snapshot is mounted at
+    pub fn mount_dir(&self) -> &Path {
+        &self.mount_dir
+    }
+
+    /// Create and mount the snapshot
+    pub fn create(&mut self) -> Result<()> {
+        run(self.create_command(), "Creating filesystem snapshot").wrap_err_with(|| {
+            format!(
+                "Snapshot {} of {} failed, a leftover of an aborted run has to be removed manually",
+                self.snapshot(),
+                self.volume
+            )
+        })?;
+        self.created = true;
+        std::fs::create_dir_all(&self.mount_dir)
+            .into_diagnostic()
+            .wrap_err("Creating snapshot mount directory")?;
+        run(self.mount_command(), "Mounting filesystem snapshot")?;
+        self.mounted = true;
+        Ok(())
+    }
+
+    /// Unmount and remove the snapshot, as far as it was set up
+    pub fn teardown(&mut self) -> Result<()> {
+        if self.mounted {
+            let mut cmd = Command::new("umount");
+            cmd.arg(&self.mount_dir);
+            run(cmd, "Unmounting filesystem snapshot")?;
+            self.mounted = false;
+        }
+        if self.created {
+            run(self.destroy_command(), "Removing filesystem snapshot")?;
+            self.created = false;
+        }
+        Ok(())
+    }
+
+    /// Snapshot identifier as used by the tools
+    fn snapshot(&self) -> String {
+        match self.kind {
+            FsSnapshotKind::Lvm => {
+                let vg = self.volume.split_once('/').map_or("", |(vg, _)| vg);
+                format!("{vg}/{}", self.name)
+            }
+            FsSnapshotKind::Zfs => format!("{}@{}", self.volume, self.name),
+            FsSnapshotKind::Btrfs => Path::new(&self.volume)
+                .join(format!(".{}", self.name))
+                .to_string_lossy()
+                .into_owned(),
+        }
+    }
+
+    fn create_command(&self) -> Command {
+        match self.kind {
+            FsSnapshotKind::Lvm => {
+                let mut cmd = Command::new("lvcreate");
+                cmd.args(["--snapshot", "--size"])
+                    .arg(self.size.as_deref().unwrap_or_default())
+                    .args(["--name", &self.name, &self.volume]);
+                cmd
+            }
+            FsSnapshotKind::Zfs => {
+                let mut cmd = Command::new("zfs");
+                cmd.args(["snapshot", &self.snapshot()]);
+                cmd
+            }
+            FsSnapshotKind::Btrfs => {
+                // read-only snapshot inside the subvolume, not part of the snapshot itself
+                let mut cmd = Command::new("btrfs");
+                cmd.args([
+                    "subvolume",
+                    "snapshot",
+                    "-r",
+                    &self.volume,
+                    &self.snapshot(),
+                ]);
+                cmd
+            }
+        }
+    }
+
+    fn mount_command(&self) -> Command {
+        let mut options = String::from("ro");
+        if let Some(extra) = &self.mount_options {
+            options.push(',');
+            options.push_str(extra);
+        }
+        let mut cmd = Command::new("mount");
+        match self.kind {
+            FsSnapshotKind::Lvm => {
+                cmd.args(["-o", &options])
+                    .arg(format!("/dev/{}", self.snapshot()));
+            }
+            FsSnapshotKind::Zfs => {
+                cmd.args(["-t", "zfs", "-o", &options, &self.snapshot()]);
+            }
+            FsSnapshotKind::Btrfs => {
+                cmd.args(["--bind", &self.snapshot()]);
+            }
+        }
+        cmd.arg(&self.mount_dir);
+        cmd
+    }
+
+    fn destroy_command(&self) -> Command {
+        let mut cmd;
+        match self.kind {
+            FsSnapshotKind::Lvm => {
+                cmd = Command::new("lvremove");
+                cmd.args(["--yes", &self.snapshot()]);
+            }
+            FsSnapshotKind::Zfs => {
+                cmd = Command::new("zfs");
+                cmd.args(["destroy", &self.snapshot()]);
+            }
+            FsSnapshotKind::Btrfs => {
+                cmd = Command::new("btrfs");
+                cmd.args(["subvolume", "delete", &self.snapshot()]);
+            }
+        }
+        cmd
+    }
+}
+
+/// Run command, failing with its stderr on non-zero exit code
+fn run(mut cmd: Command, action: &'static str) -> Result<()> {
+    let output = cmd
+        .output()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Starting {:?}", cmd.get_program()))?;
+    if !output.status.success() {
+        bail!(
+            "{} failed, exit code {}: {}",
+            action,
+            output.status.code().unwrap_or(0),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(cmd: &Command) -> Vec<String> {
+        std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|v| v.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_commands() {
+        let config = FsSnapshot {
+            kind: FsSnapshotKind::Lvm,
+            volume: String::from("vg0/data"),
+            size: Some(String::from("5G")),
+            mountpoint: PathBuf::from("/srv"),
+            mount_options: Some(String::from("nouuid")),
+        };
+        let snapshot = ActiveSnapshot::new("my job", &config, PathBuf::from("/scratch/mnt"));
+        assert_eq!(
+            args(&snapshot.create_command()),
+            [
+                "lvcreate",
+                "--snapshot",
+                "--size",
+                "5G",
+                "--name",
+                "backuprs_my_job",
+                "vg0/data"
+            ]
+        );
+        assert_eq!(
+            args(&snapshot.mount_command()),
+            [
+                "mount",
+                "-o",
+                "ro,nouuid",
+                "/dev/vg0/backuprs_my_job",
+                "/scratch/mnt"
+            ]
+        );
+        assert_eq!(
+            args(&snapshot.destroy_command()),
+            ["lvremove", "--yes", "vg0/backuprs_my_job"]
+        );
+
+        let config = FsSnapshot {
+            kind: FsSnapshotKind::Zfs,
+            volume: String::from("tank/data"),
+            size: None,
+            mountpoint: PathBuf::from("/srv"),
+            mount_options: None,
+        };
+        let snapshot = ActiveSnapshot::new("job", &config, PathBuf::from("/scratch/mnt"));
+        assert_eq!(
+            args(&snapshot.create_command()),
+            ["zfs", "snapshot", "tank/data@backuprs_job"]
+        );
+        assert_eq!(
+            args(&snapshot.mount_command()),
+            [
+                "mount",
+                "-t",
+                "zfs",
+                "-o",
+                "ro",
+                "tank/data@backuprs_job",
+                "/scratch/mnt"
+            ]
+        );
+
+        let config = FsSnapshot {
+            kind: FsSnapshotKind::Btrfs,
+            volume: String::from("/srv"),
+            size: None,
+            mountpoint: PathBuf::from("/srv"),
+            mount_options: None,
+        };
+        let snapshot = ActiveSnapshot::new("job", &config, PathBuf::from("/scratch/mnt"));
+        assert_eq!(
+            args(&snapshot.create_command()),
+            [
+                "btrfs",
+                "subvolume",
+                "snapshot",
+                "-r",
+                "/srv",
+                "/srv/.backuprs_job"
+            ]
+        );
+        assert_eq!(
+            args(&snapshot.destroy_command()),
+            ["btrfs", "subvolume", "delete", "/srv/.backuprs_job"]
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use std::io;
+use std::path::Path;
+
+use miette::{Context, IntoDiagnostic, Result};
+
+/// Restic exclude patterns for all `.gitignore` files below `root`, best-effort.
+///
+/// Patterns of parent directories come first, so rules of nested files take precedence.
+/// Negated rules are returned with a leading `!`. Symlinks and `.git` directories are
+/// not followed, unreadable directories and `.gitignore` files are passed to `on_skip`.
+pub fn exclude_patterns(
+    root: &Path,
+    mut on_skip: impl FnMut(&Path, io::Error),
+) -> Result<Vec<String>> {
+    let root = std::path::absolute(root)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Resolving path {}", root.display()))?;
+    let mut patterns = Vec::new();
+    let mut dirs = vec![root];
+    while let Some(dir) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(v) => v,
+            Err(e) => {
+                on_skip(&dir, e);
+                continue;
+            }
+        };
+        let mut children = Vec::new();
+        for entry in entries.filter_map(|v| v.ok()) {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() && entry.file_name() != ".git" {
+                children.push(entry.path());
+            } else if file_type.is_file() && entry.file_name() == ".gitignore" {
+                let path = entry.path();
+                let content = match std::fs::read_to_string(&path) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        on_skip(&path, e);
+                        continue;
+                    }
+                };
+                let base = pattern_base(&dir);
+                patterns.extend(content.lines().filter_map(|line| convert(&base, line)));
+            }
+        }
+        // reversed for walking in name order, purely cosmetic
+        children.sort_unstable_by(|a, b| b.cmp(a));
+        dirs.append(&mut children);
+    }
+    Ok(patterns)
+}
+
+/// Directory as restic pattern prefix, with glob characters escaped
+fn pattern_base(dir: &Path) -> String {
+    let dir = dir.to_string_lossy();
+    #[cfg(windows)]
+    let base = dir.replace('\\', "/");
+    #[cfg(not(windows))]
+    let base = dir.chars().fold(String::new(), |mut acc, c| {
+        if matches!(c, '*' | '?' | '[' | '\\') {
+            acc.push('\\');
+        }
+        acc.push(c);
+        acc
+    });
+    base.trim_end_matches('/').to_owned()
+}
+
+/// Convert one `.gitignore` line of the directory `base` into a restic pattern
+fn convert(base: &str, line: &str) -> Option<String> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negated, line) = match line.strip_prefix('!') {
+        Some(line) => (true, line),
+        None => (false, line),
+    };
+    // escaped leading `#` or `!`
+    let line = line.strip_prefix('\\').unwrap_or(line);
+    // restic can't restrict patterns to directories
+    let line = line.strip_suffix('/').unwrap_or(line);
+    if line.is_empty() {
+        return None;
+    }
+    // patterns with a slash are relative to the .gitignore, others match at any depth
+    let pattern = match line.contains('/') {
+        true => format!("{}/{}", base, line.trim_start_matches('/')),
+        false => format!("{}/**/{}", base, line),
+    };
+    Some(match negated {
+        true => format!("!{pattern}"),
+        false => pattern,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_convert() {
+        let base = "/src/app";
+        assert_eq!(convert(base, "# comment"), None);
+        assert_eq!(convert(base, "   "), None);
+        assert_eq!(convert(base, "/"), None);
+        assert_eq!(convert(base, "*.log").unwrap(), "/src/app/**/*.log");
+        assert_eq!(convert(base, "target/").unwrap(), "/src/app/**/target");
+        assert_eq!(convert(base, "/build").unwrap(), "/src/app/build");
+        assert_eq!(convert(base, "docs/*.pdf").unwrap(), "/src/app/docs/*.pdf");
+        assert_eq!(convert(base, "**/tmp").unwrap(), "/src/app/**/tmp");
+        assert_eq!(convert(base, "!keep.log").unwrap(), "!/src/app/**/keep.log");
+        assert_eq!(convert(base, "\\#file").unwrap(), "/src/app/**/#file");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exclude_patterns() {
+        let root = std::env::temp_dir().join(format!("backuprs_gitignore_{}", std::process::id()));
+        std::fs::create_dir_all(root.join("sub/.git")).unwrap();
+        std::fs::write(root.join(".gitignore"), "*.log\n").unwrap();
+        std::fs::write(root.join("sub/.gitignore"), "!keep.log\n/out/\n").unwrap();
+        std::fs::write(root.join("sub/.git/.gitignore"), "ignored\n").unwrap();
+        // not valid UTF-8, skipped like unreadable files
+        std::fs::create_dir_all(root.join("binary")).unwrap();
+        std::fs::write(root.join("binary/.gitignore"), b"\xff\xfe\n").unwrap();
+
+        let base = root.to_string_lossy();
+        let mut skipped = Vec::new();
+        assert_eq!(
+            exclude_patterns(&root, |path, _| skipped.push(path.to_owned())).unwrap(),
+            [
+                format!("{base}/**/*.log"),
+                format!("!{base}/sub/**/keep.log"),
+                format!("{base}/sub/out"),
+            ]
+        );
+        assert_eq!(skipped, [root.join("binary/.gitignore")]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,253 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use miette::{bail, Context, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime, UtcOffset};
+
+use crate::config::Global;
+use crate::job::Job;
+use crate::models::{format_duration, format_size, BackupSummary};
+use crate::notify::error_chain;
+
+/// Result of one backup run, a line in the history file of the job
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Time the run finished
+    #[serde(with = "time::serde::rfc3339")]
+    pub timestamp: OffsetDateTime,
+    pub job: String,
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<BackupSummary>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl HistoryEntry {
+    fn new(job: &Job, result: &Result<BackupSummary>) -> Self {
+        Self {
+            timestamp: OffsetDateTime::now_utc(),
+            job: job.name().to_owned(),
+            success: result.is_ok(),
+            summary: result.as_ref().ok().cloned(),
+            error: result.as_ref().err().map(error_chain),
+        }
+    }
+}
+
+/// History file of a job inside the `history_dir`
+fn history_path(dir: &Path, job: &str) -> PathBuf {
+    // never outside of the history_dir, for names containing `/` or `..`
+    let job: String = job
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '-' {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    dir.join(format!("{job}.jsonl"))
+}
+
+/// Append the result of a run to the history of the job, if `history_dir` is configured.
+///
+/// Errors are only logged, the backup never fails because of its history.
+pub fn record_logged(global: &Global, job: &Job, result: &Result<BackupSummary>) {
+    let Some(dir) = &global.history_dir else {
+        return;
+    };
+    let path = history_path(dir, job.name());
+    let entry = HistoryEntry::new(job, result);
+    if let Err(e) = append(&path, &entry, global.history_retention) {
+        eprintln!("[{}]\tFailed to write history file: {:?}", job.name(), e);
+    }
+}
+
+/// Append entry as one line, keeping only the last `retention` entries
+fn append(path: &Path, entry: &HistoryEntry, retention: Option<usize>) -> Result<()> {
+    let mut line = serde_json::to_vec(entry).into_diagnostic()?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Opening history file {}", path.display()))?;
+    // a crash can only leave a partial last line, which is skipped on read
+    if ends_partial(&mut file)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Reading history file {}", path.display()))?
+    {
+        line.insert(0, b'\n');
+    }
+    file.write_all(&line)
+        .and_then(|_| file.sync_data())
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Writing history file {}", path.display()))?;
+    drop(file);
+    if let Some(retention) = retention {
+        truncate(path, retention)?;
+    }
+    Ok(())
+}
+
+/// Whether the file doesn't end with a newline
+fn ends_partial(file: &mut File) -> std::io::Result<bool> {
+    let len = file.metadata()?.len();
+    if len == 0 {
+        return Ok(false);
+    }
+    let mut last = [0u8];
+    file.seek(SeekFrom::Start(len - 1))?;
+    file.read_exact(&mut last)?;
+    Ok(last[0] != b'\n')
+}
+
+/// Remove all but the last `retention` lines, atomically via temporary file and rename.
+fn truncate(path: &Path, retention: usize) -> Result<()> {
+    let data = std::fs::read_to_string(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Reading history file {}", path.display()))?;
+    let lines: Vec<&str> = data.lines().collect();
+    if lines.len() <= retention {
+        return Ok(());
+    }
+    let mut kept = lines[lines.len() - retention..].join("\n");
+    kept.push('\n');
+    let tmp_path = path.with_extension("jsonl.tmp");
+    std::fs::write(&tmp_path, kept)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Writing history file {}", tmp_path.display()))?;
+    std::fs::rename(&tmp_path, path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Moving history file to {}", path.display()))?;
+    Ok(())
+}
+
+/// Last `limit` entries of a history file, oldest first.
+///
+/// Unreadable lines, for example from a crash while writing, are skipped with a warning.
+fn read(path: &Path, limit: usize) -> Result<Vec<HistoryEntry>> {
+    let data = std::fs::read_to_string(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Reading history file {}", path.display()))?;
+    let mut entries: Vec<HistoryEntry> = data
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(i, line)| match serde_json::from_str(line) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                eprintln!(
+                    "Skipping invalid line {} of history file {}: {}",
+                    i + 1,
+                    path.display(),
+                    e
+                );
+                None
+            }
+        })
+        .collect();
+    let skip = entries.len().saturating_sub(limit);
+    entries.drain(..skip);
+    Ok(entries)
+}
+
+/// Print the last `limit` runs of a job as table or JSON
+pub fn print_history(global: &Global, job: &str, limit: usize, json: bool) -> Result<()> {
+    let Some(dir) = &global.history_dir else {
+        bail!("No history_dir configured, history isn't recorded!");
+    };
+    let path = history_path(dir, job);
+    if !path.exists() {
+        bail!("No history recorded for job '{}'", job);
+    }
+    let entries = read(&path, limit)?;
+
+    if json {
+        let out = serde_json::to_string_pretty(&entries).into_diagnostic()?;
+        println!("{}", out);
+        return Ok(());
+    }
+
+    let offset = global.now().offset();
+    println!(
+        "{:16}  {:6}  {:>8}  {:>10}  {:>10}  {:>8}  SNAPSHOT",
+        "FINISHED", "RESULT", "DURATION", "ADDED", "PROCESSED", "FILES"
+    );
+    for entry in entries.iter() {
+        print!(
+            "{:16}  {:6}",
+            format_timestamp(entry.timestamp, offset),
+            if entry.success { "ok" } else { "failed" }
+        );
+        match &entry.summary {
+            Some(summary) => println!(
+                "  {:>8}  {:>10}  {:>10}  {:>8}  {}",
+                format_duration(Duration::seconds_f32(summary.total_duration)),
+                format_size(summary.data_added),
+                format_size(summary.total_bytes_processed),
+                summary.total_files_processed,
+                summary.snapshot_id.get(..8).unwrap_or(&summary.snapshot_id)
+            ),
+            None => println!(),
+        }
+        if let Some(error) = entry.error.as_deref().and_then(|v| v.lines().next()) {
+            println!("{:16}  error: {}", "", error);
+        }
+    }
+    Ok(())
+}
+
+/// Timestamp as `YYYY-MM-DD HH:MM` in the given offset
+fn format_timestamp(time: OffsetDateTime, offset: UtcOffset) -> String {
+    let time = time.to_offset(offset);
+    format!("{} {:02}:{:02}", time.date(), time.hour(), time.minute())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_history_retention() {
+        let dir = std::env::temp_dir().join(format!("backuprs_history_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = history_path(&dir, "job");
+        let entry = |error: &str| HistoryEntry {
+            timestamp: OffsetDateTime::UNIX_EPOCH,
+            job: String::from("job"),
+            success: false,
+            summary: None,
+            error: Some(error.to_owned()),
+        };
+        for i in 0..5 {
+            append(&path, &entry(&i.to_string()), Some(3)).unwrap();
+        }
+        // partial line of an interrupted write
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"timestamp\":").unwrap();
+        let entries = read(&path, 10).unwrap();
+        let errors: Vec<_> = entries.iter().filter_map(|v| v.error.as_deref()).collect();
+        assert_eq!(errors, ["2", "3", "4"]);
+
+        append(&path, &entry("5"), None).unwrap();
+        let entries = read(&path, 2).unwrap();
+        let errors: Vec<_> = entries.iter().filter_map(|v| v.error.as_deref()).collect();
+        assert_eq!(errors, ["4", "5"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_history_path() {
+        let dir = Path::new("/var/lib/backuprs/history");
+        assert_eq!(history_path(dir, "web-1"), dir.join("web-1.jsonl"));
+        assert_eq!(
+            history_path(dir, "../etc/cron"),
+            dir.join("___etc_cron.jsonl")
+        );
+        assert_eq!(history_path(dir, "db files"), dir.join("db_files.jsonl"));
+    }
+}
@@ -1,30 +1,82 @@
 use std::{
+    collections::HashMap,
+    ffi::OsString,
     fs::File,
     io::{BufReader, Read},
+    path::{Path, PathBuf},
     process::Command,
+    time::Instant,
 };
 
 use clap::{Parser, Subcommand};
-use config::{Conf, Global};
-use miette::{bail, Context, IntoDiagnostic, Result};
-use time::{OffsetDateTime, Time};
+use config::{BackupTimeRange, Conf, Global, ResticVersion};
+use config_format::ConfigFormat;
+use miette::{bail, miette, Context, IntoDiagnostic, Result};
+use time::{OffsetDateTime, Time, UtcOffset};
 
 use crate::error::CommandError;
+use crate::job::Job;
+use crate::metrics::Metrics;
+use crate::models::{format_duration, format_relative, format_size, BackupSummary};
+use crate::notify::Task;
+use crate::state::State;
+
+/// `println!` prefixed with the timestamp of the [Global] config, if enabled
+macro_rules! log_println {
+    ($global:expr, $($arg:tt)*) => {
+        println!("{}{}", $global.log_prefix(), format_args!($($arg)*))
+    };
+}
+
+/// `eprintln!` prefixed with the timestamp of the [Global] config, if enabled
+macro_rules! log_eprintln {
+    ($global:expr, $($arg:tt)*) => {
+        eprintln!("{}{}", $global.log_prefix(), format_args!($($arg)*))
+    };
+}
 
 mod config;
+mod config_format;
 mod error;
+mod fs_snapshot;
+mod gitignore;
+mod history;
 mod job;
+mod metrics;
 mod models;
+mod notify;
+mod permissions;
+mod schedule;
+mod state;
+
+/// Exit code of `run` if a backup failed because its repository is full
+const EXIT_REPOSITORY_FULL: i32 = 3;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    /// Verbose output
+    /// Verbose output. 1 passes --verbose to restic, 2 and higher
+    /// pass --verbose=2 for restic's per file output.
     #[arg(short, long, default_value_t = 0)]
     verbose: usize,
+    /// Only print errors, no progress or status of jobs. A successful run prints nothing.
+    #[arg(short, long, default_value_t = false, conflicts_with = "verbose")]
+    quiet: bool,
     /// Disable progress output for backups.
     #[arg(short, long, default_value_t = false)]
     no_progress: bool,
+    /// Minimum seconds between progress lines, overrides `progress_interval_secs`.
+    #[arg(long)]
+    progress_interval: Option<u64>,
+    /// Disable all notifications and healthcheck pings, for manual runs.
+    #[arg(long, default_value_t = false)]
+    no_notify: bool,
+    /// Configuration file, overrides $BACKUPRS_CONFIG and the default search paths.
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Only warn about insecure permissions or ownership of the configuration file, instead of aborting.
+    #[arg(long, default_value_t = false, alias = "insecure-config")]
+    allow_insecure_config: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -35,24 +87,148 @@ enum Commands {
     Test {
         /// Dry run, do not perform backup, only print what would happen.
         ///
-        /// Equals `restic backup --dry-run`. Requires job argument.
+        /// Equals `restic backup --dry-run`, prints the restic command line. Requires job argument.
         #[arg(long, default_value_t = false)]
         dry_run: bool,
-        /// Test specific job by name
+        /// Test specific job by name or glob pattern like `db-*`
         #[arg(short, long)]
         job: Option<String>,
+        /// Send a test message via all configured notification channels
+        #[arg(long, default_value_t = false)]
+        notify: bool,
+        /// Also list all snapshots, to show their count and the oldest one. Slower for large repositories.
+        #[arg(long, default_value_t = false)]
+        full: bool,
     },
     /// Force run all or one backup job
     Run {
-        /// Run specific job by name
+        /// Run specific job by name or all jobs matching a glob pattern like `db-*`
         #[arg(short, long)]
         job: Option<String>,
         /// Abort on first error, stops any further jobs
         #[arg(short, long, default_value_t = false)]
         abort_on_error: bool,
+        /// Backup the newline separated paths from stdin instead of the configured ones.
+        ///
+        /// Requires job argument.
+        #[arg(long, default_value_t = false, requires = "job")]
+        paths_from_stdin: bool,
+        /// Timestamp of the snapshot instead of now, `YYYY-MM-DD HH:MM:SS`, for importing old data.
+        ///
+        /// Equals `restic backup --time`. Requires job argument.
+        #[arg(long, requires = "job", value_parser = parse_snapshot_time)]
+        time: Option<String>,
+        /// Run at most N due jobs, those with the oldest next run first. For periodic runs via cron.
+        ///
+        /// Jobs that aren't due are skipped, the remaining due jobs are deferred.
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        max_jobs: Option<u64>,
     },
     /// Daemonize and run backups in specified intervals
-    Daemon {},
+    Daemon {
+        /// Immediately backup jobs whose next run is already in the past, ignoring the backup period.
+        ///
+        /// Catches up backups missed while the machine was off.
+        #[arg(long, default_value_t = false)]
+        run_missed: bool,
+    },
+    /// Validate the configuration only, without accessing any repository
+    Validate {},
+    /// Copy snapshots from the repository of one job to the repository of another
+    Copy {
+        /// Source job name
+        from: String,
+        /// Destination job name
+        to: String,
+        /// Snapshot IDs to copy, defaults to all snapshots
+        snapshots: Vec<String>,
+    },
+    /// Print the effective configuration of all or one job, with global defaults applied
+    Config {
+        /// Show specific job by name
+        #[arg(short, long)]
+        job: Option<String>,
+    },
+    /// Verify the integrity of all or one repository via `restic check`
+    Check {
+        /// Check specific job by name
+        #[arg(short, long)]
+        job: Option<String>,
+        /// Also read and verify a subset of the data, `10%` or `1/5`.
+        ///
+        /// Equals `restic check --read-data-subset`.
+        #[arg(long)]
+        read_data_subset: Option<String>,
+    },
+    /// Show the size of all or one repository via `restic stats`
+    Stats {
+        /// Show specific job by name
+        #[arg(short, long)]
+        job: Option<String>,
+    },
+    /// Show the changes between two snapshots of a job via `restic diff`
+    Diff {
+        /// Job name
+        job: String,
+        /// Snapshot to compare from, defaults to the second latest snapshot
+        from: Option<String>,
+        /// Snapshot to compare to, defaults to the latest snapshot
+        to: Option<String>,
+        /// Print every changed path, also enabled by --verbose
+        #[arg(long, default_value_t = false)]
+        full: bool,
+        /// Amount of the largest added files to show
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+    /// List the contents of a snapshot via `restic ls`
+    Ls {
+        /// Job name
+        job: String,
+        /// Snapshot ID
+        #[arg(default_value = "latest")]
+        snapshot: String,
+        /// Directory inside the snapshot to list
+        path: Option<PathBuf>,
+    },
+    /// Mount the repository of a job via `restic mount` for browsing and restores
+    Mount {
+        /// Job name
+        #[arg(short, long)]
+        job: String,
+        /// Empty directory to mount at
+        target: PathBuf,
+    },
+    /// Replace the key of a job repository, adds the new key and removes the old one after verifying it
+    RotateKey {
+        /// Job name
+        job: String,
+        /// File containing the new key
+        new_key_file: PathBuf,
+    },
+    /// Show the recorded runs of a job, requires `history_dir`
+    History {
+        /// Job name
+        job: String,
+        /// Number of most recent runs to show
+        #[arg(short, long, default_value_t = 30)]
+        limit: usize,
+        /// Output as JSON
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
+    /// Show last and next run of all jobs, from the state file
+    Status {
+        /// Output as JSON
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
+    /// Print an example configuration, doesn't require an existing one
+    GenerateConfig {
+        /// Output format, the config file format is chosen by its extension
+        #[arg(long, value_enum, default_value_t = ConfigFormat::Toml)]
+        format: ConfigFormat,
+    },
 }
 
 // /// Turn debugging information on
@@ -66,16 +242,56 @@ enum Commands {
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
-    let mut config = read_config().wrap_err("Reading configuration")?;
+    if let Commands::GenerateConfig { format } = cli.command {
+        print!("{}", config_format::generate(format)?);
+        return Ok(());
+    }
+
+    let config_path = find_config(cli.config.clone())?;
+    if cli.verbose > 0 {
+        println!("Loading configuration {}", config_path.display());
+    }
+    let mut config = read_config(&config_path, cli.allow_insecure_config)
+        .wrap_err_with(|| format!("Reading configuration {}", config_path.display()))?;
     if cli.verbose > 0 {
         config.global.verbose = cli.verbose;
+        config.global.verbose_floor = cli.verbose;
     }
     if cli.no_progress {
         config.global.progress = false;
     }
+    if cli.quiet {
+        config.global.quiet = true;
+        config.global.progress = false;
+    }
+    if let Some(interval) = cli.progress_interval {
+        config.global.progress_interval_secs = interval;
+    }
+    if cli.no_notify {
+        config.global.no_notify = true;
+    }
+
+    if let Commands::Validate {} = &cli.command {
+        let jobs = config.job.len();
+        let errors = config.validate();
+        if errors.is_empty() {
+            println!("Configuration valid, {} jobs.", jobs);
+            return Ok(());
+        }
+        for e in errors.iter() {
+            eprintln!("{:?}", e);
+        }
+        bail!("Found {} configuration errors", errors.len());
+    }
 
     config.global.check()?;
-    check_restic(&config.global)?;
+    config
+        .global
+        .init_log_timestamps(matches!(cli.command, Commands::Daemon { .. }))?;
+    // only prints the configuration
+    if !matches!(cli.command, Commands::Config { .. }) {
+        check_restic(&mut config)?;
+    }
     // TODO: fail on duplicate job names
     let (defaults, mut jobs) = config.split()?;
 
@@ -87,50 +303,131 @@ fn main() -> Result<()> {
         Commands::Run {
             job,
             abort_on_error: _,
+            paths_from_stdin,
+            time,
+            max_jobs,
         } => {
-            // maybe unify this here, but would require creating an ad-hoc iterator of
-            // one element
-            if let Some(jobname) = job {
-                if let Some(job) = jobs.get_mut(jobname) {
-                    match job.backup() {
-                        Ok(_) => (),
-                        Err(e) => {
-                            eprintln!("[{}] Failed to backup.", job.name());
-                            return Err(e);
-                        }
+            let mut metrics = init_metrics(&defaults, jobs.values());
+            let mut state = State::load_logged(&defaults);
+            // an exact name keeps the single job behavior, everything else is a pattern
+            if let Some(job) = job.as_ref().and_then(|name| jobs.get_mut(name)) {
+                if !job.enabled() {
+                    bail!(
+                        "Job '{}' is disabled, enable it to run a backup.",
+                        job.name()
+                    );
+                }
+                if *paths_from_stdin {
+                    job.set_paths(read_stdin_paths()?);
+                }
+                if let Some(time) = time {
+                    job.set_snapshot_time(time.clone());
+                }
+                if let Err(e) = backup_notify(job, &defaults, &mut metrics, &mut state) {
+                    let full = CommandError::is_repository_full(&e);
+                    let e = e.wrap_err(format!("[{}] backup failed", job.name()));
+                    if full {
+                        eprintln!("Error: {:?}", e);
+                        std::process::exit(EXIT_REPOSITORY_FULL);
                     }
-                } else {
-                    bail!("No job named '{}' found!", jobname);
+                    return Err(e);
                 }
             } else {
+                let mut names = match job {
+                    Some(pattern) => matching_jobs(jobs.keys(), pattern)?,
+                    None => jobs.keys().cloned().collect(),
+                };
+                if *paths_from_stdin {
+                    bail!(
+                        "Reading paths from stdin requires a single job, '{}' matches {} jobs.",
+                        job.as_deref().unwrap_or_default(),
+                        names.len()
+                    );
+                }
+                if let Some(max) = max_jobs {
+                    let next_runs = names
+                        .iter()
+                        .map(|name| jobs.get(name).expect("matched job exists"))
+                        .filter(|job| job.enabled())
+                        .map(|job| Ok((job.name().to_owned(), expected_run(job, &state)?)))
+                        .collect::<Result<Vec<_>>>()?;
+                    let (deferred, not_due);
+                    (names, deferred, not_due) =
+                        most_overdue(next_runs, defaults.now(), *max as usize);
+                    for name in not_due.iter() {
+                        log_println!(defaults, "[{}]\tNot due yet, skipping.", name);
+                    }
+                    for name in deferred.iter() {
+                        log_println!(
+                            defaults,
+                            "[{}]\tDeferred, exceeding --max-jobs {}.",
+                            name,
+                            max
+                        );
+                    }
+                }
+                let selected = job.is_some();
                 let mut run = 0;
                 let mut failed = 0;
-                for job in jobs.values_mut() {
-                    match job.backup() {
-                        Ok(_) => (),
+                let mut full = false;
+                for name in names {
+                    let job = jobs.get_mut(&name).expect("matched job exists");
+                    if !job.enabled() {
+                        if selected && !defaults.quiet {
+                            println!("[{}]\tJob disabled, skipping.", name);
+                        }
+                        continue;
+                    }
+                    if let Some(time) = time {
+                        job.set_snapshot_time(time.clone());
+                    }
+                    match backup_notify(job, &defaults, &mut metrics, &mut state) {
+                        Ok(_) if defaults.quiet => (),
+                        Ok(summary) if !summary.errors.is_empty() => {
+                            println!("[{}]\tBackup completed with warnings.", name)
+                        }
+                        Ok(_) => println!("[{}]\tBackup successful.", name),
                         Err(e) => {
                             failed += 1;
-                            eprintln!("[{}]\tFailed to backup. {}", job.name(), e);
+                            full |= CommandError::is_repository_full(&e);
+                            eprintln!("[{}]\tFailed to backup. {}", name, e);
                         }
                     }
                     run += 1;
                 }
-                println!("Backup run finished. {}/{} jobs failed.", failed, run);
+                if !defaults.quiet {
+                    println!("Backup run finished. {}/{} jobs failed.", failed, run);
+                } else if failed > 0 {
+                    eprintln!("Backup run finished. {}/{} jobs failed.", failed, run);
+                }
+                if full {
+                    std::process::exit(EXIT_REPOSITORY_FULL);
+                }
             }
         }
-        Commands::Test { dry_run, job } => {
+        Commands::Test {
+            dry_run,
+            job,
+            notify,
+            full,
+        } => {
             let mut failed = 0;
+            if *notify {
+                return notify::test(&defaults);
+            }
             if *dry_run {
                 match job {
-                    Some(target_name) => {
+                    Some(pattern) => {
                         println!("Dry run mode.");
-                        for (name, job) in jobs.iter_mut() {
-                            if name == target_name {
-                                job.dry_run()?;
-                                return Ok(());
-                            }
+                        if let Some(job) = jobs.get_mut(pattern) {
+                            return job.dry_run();
                         }
-                        bail!("No job named '{}' found!", target_name);
+                        for name in matching_jobs(jobs.keys(), pattern)? {
+                            let job = jobs.get_mut(&name).expect("matched job exists");
+                            job.dry_run()
+                                .wrap_err_with(|| format!("[{}] dry run failed", name))?;
+                        }
+                        return Ok(());
                     }
                     None => {
                         bail!("Dry run flag requires a job name!");
@@ -151,88 +448,568 @@ fn main() -> Result<()> {
                 ),
             }
             // println!("Backup starting time is {}",defaults.backup_start_time);
-            for (_, job) in jobs.iter_mut() {
-                match job.update_last_run() {
-                    Ok(_) => {
-                        let next_run = job.next_run()?;
-                        println!(
-                        "[{}]\tJob ok, found snapshots, last backup {}, next backup would be at {}",
+            let state = State::load_logged(&defaults);
+            let now = defaults.now();
+            let names = match job {
+                Some(pattern) if !jobs.contains_key(pattern) => {
+                    matching_jobs(jobs.keys(), pattern)?
+                }
+                Some(name) => vec![name.clone()],
+                None => jobs.keys().cloned().collect(),
+            };
+            let mut repo_ids: HashMap<String, Vec<String>> = HashMap::new();
+            for name in names {
+                let job = jobs.get_mut(&name).expect("matched job exists");
+                if !job.enabled() {
+                    println!("[{}]\tJob disabled, skipping.", job.name());
+                    continue;
+                }
+                if let Some(next_prune) = next_prune(job, &state, now, now.offset()) {
+                    println!(
+                        "[{}]\tNext prune expected {}, {}",
                         job.name(),
-                        job.last_run().expect("Expected at least one snapshot"),
-                        next_run,
+                        format_relative(now, next_prune.max(now)),
+                        job.prune_schedule().unwrap()
                     );
+                    if job.verbosity() > 0 {
+                        println!("[{}]\tNext prune at {}", job.name(), next_prune.max(now));
                     }
-                    Err(e) => {
-                        if e == CommandError::NotInitialized {
-                            println!("[{}]\tRepo not initialized?", job.name());
-                        } else {
-                            eprintln!("[{}]\tCheck failed: {}", job.name(), e);
-                            failed += 1;
+                }
+                match job.latest_snapshot() {
+                    Ok(latest) => {
+                        let next_run = job.next_run()?;
+                        match latest {
+                            Some(snapshot) => {
+                                let last_run = snapshot.time;
+                                println!(
+                                    "[{}]\tJob ok, found snapshots, last backup {}, next backup {}",
+                                    job.name(),
+                                    format_relative(now, last_run),
+                                    format_relative(now, next_run),
+                                );
+                                if job.verbosity() > 0 {
+                                    println!(
+                                        "[{}]\tLast backup at {}, next backup would be at {}",
+                                        job.name(),
+                                        last_run,
+                                        next_run
+                                    );
+                                }
+                                let tags = match snapshot.tags.is_empty() {
+                                    true => String::from("none"),
+                                    false => snapshot.tags.join(", "),
+                                };
+                                let program = snapshot
+                                    .program_version
+                                    .as_deref()
+                                    .map(|v| format!(", created by {}", v))
+                                    .unwrap_or_default();
+                                println!(
+                                    "[{}]\tLatest snapshot {}, tags: {}{}",
+                                    job.name(),
+                                    snapshot.short_id(),
+                                    tags,
+                                    program
+                                );
+                                let age = OffsetDateTime::now_utc() - last_run;
+                                if let Some(max_age) =
+                                    job.max_age().filter(|max_age| age > *max_age)
+                                {
+                                    eprintln!(
+                                        "[{}]\tWarning: last snapshot is {} old, exceeding max_age of {}!",
+                                        job.name(),
+                                        format_duration(age),
+                                        format_duration(max_age)
+                                    );
+                                    failed += 1;
+                                }
+                            }
+                            None => {
+                                println!(
+                                    "[{}]\tJob ok, no snapshots yet, next backup {}",
+                                    job.name(),
+                                    format_relative(now, next_run),
+                                );
+                                if let Some(max_age) = job.max_age() {
+                                    eprintln!(
+                                        "[{}]\tWarning: no snapshots yet, exceeding max_age of {}!",
+                                        job.name(),
+                                        format_duration(max_age)
+                                    );
+                                    failed += 1;
+                                }
+                            }
+                        }
+                        match job.repo_id() {
+                            Ok(config) => {
+                                println!(
+                                    "[{}]\tRepository ID {}, version {}",
+                                    job.name(),
+                                    config.id,
+                                    config.version
+                                );
+                                repo_ids.entry(config.id).or_default().push(name.clone());
+                            }
+                            Err(e) => {
+                                eprintln!("[{}]\tFailed to read repository ID: {}", job.name(), e)
+                            }
+                        }
+                        if *full {
+                            match job.snapshots(None) {
+                                Ok(snapshots) => {
+                                    match snapshots.iter().map(|snapshot| snapshot.time).min() {
+                                        Some(oldest) => println!(
+                                            "[{}]\t{} snapshots, oldest from {}",
+                                            job.name(),
+                                            snapshots.len(),
+                                            oldest
+                                        ),
+                                        None => println!("[{}]\t0 snapshots", job.name()),
+                                    }
+                                }
+                                Err(e) => {
+                                    eprintln!("[{}]\tFailed to list snapshots: {}", job.name(), e);
+                                    failed += 1;
+                                }
+                            }
                         }
                     }
+                    Err(CommandError::NotInitialized) => {
+                        println!(
+                            "[{}]\tRepo not initialized, it will be created on the first backup.",
+                            job.name()
+                        );
+                    }
+                    Err(e @ CommandError::BackendUnreachable(_)) => {
+                        eprintln!(
+                            "[{}]\tCheck failed, verify host and credentials of the backend: {}",
+                            job.name(),
+                            e
+                        );
+                        failed += 1;
+                    }
+                    Err(e) => {
+                        eprintln!("[{}]\tCheck failed: {}", job.name(), e);
+                        failed += 1;
+                    }
+                }
+                match job.check_copy_to() {
+                    None => (),
+                    Some(Ok(())) => println!("[{}]\tSecondary repository ok.", job.name()),
+                    Some(Err(CommandError::NotInitialized)) => println!(
+                        "[{}]\tSecondary repository not initialized, it will be created on the first copy.",
+                        job.name()
+                    ),
+                    Some(Err(e)) => {
+                        eprintln!(
+                            "[{}]\tCheck of secondary repository failed: {}",
+                            job.name(),
+                            e
+                        );
+                        failed += 1;
+                    }
                 }
             }
+            let mut shared: Vec<_> = repo_ids
+                .into_iter()
+                .filter(|(_, names)| names.len() > 1)
+                .collect();
+            shared.sort();
+            for (id, mut names) in shared {
+                names.sort();
+                println!("Jobs {} use the same repository {}", names.join(", "), id);
+            }
             if failed > 0 {
-                eprintln!("Failed test for {} jobs", failed);
+                bail!("Failed test for {} jobs", failed);
             } else {
                 println!("Test successfull");
             }
         }
-        Commands::Daemon {} => {
+        Commands::Validate {} => unreachable!("handled before loading jobs"),
+        Commands::Copy {
+            from,
+            to,
+            snapshots,
+        } => {
+            if from == to {
+                bail!("Source and destination job are the same!");
+            }
+            let Some(source) = jobs.get(from) else {
+                bail!("No job named '{}' found!", from);
+            };
+            let Some(destination) = jobs.get(to) else {
+                bail!("No job named '{}' found!", to);
+            };
+            destination
+                .copy_from(source, snapshots)
+                .wrap_err_with(|| format!("Copying snapshots from '{}' to '{}'", from, to))?;
+            println!("Copied snapshots from '{}' to '{}'.", from, to);
+        }
+        Commands::Config { job } => {
+            let mut shown: Vec<&Job> = match job {
+                Some(jobname) => {
+                    let Some(job) = jobs.get(jobname) else {
+                        bail!("No job named '{}' found!", jobname);
+                    };
+                    vec![job]
+                }
+                None => jobs.values().collect(),
+            };
+            shown.sort_unstable_by_key(|job| job.name());
+            for job in shown {
+                println!("[{}]", job.name());
+                let entries = job.effective_config();
+                let width = entries.iter().map(|(k, _)| k.len()).max().unwrap_or(0);
+                for (name, value) in entries {
+                    println!("  {:width$}  {}", name, value);
+                }
+            }
+        }
+        Commands::Check {
+            job,
+            read_data_subset,
+        } => {
+            let mut state = State::load_logged(&defaults);
+            let checked: Vec<&Job> = match job {
+                Some(jobname) => {
+                    let Some(job) = jobs.get(jobname) else {
+                        bail!("No job named '{}' found!", jobname);
+                    };
+                    vec![job]
+                }
+                None => jobs.values().filter(|job| job.enabled()).collect(),
+            };
+            let mut failed = 0;
+            for job in checked.iter() {
+                let res = job.check(read_data_subset.as_deref());
+                if let Err(e) = &res {
+                    failed += 1;
+                    eprintln!("[{}]\tCheck failed: {:?}", job.name(), e);
+                }
+                state.record_check_logged(job, &res);
+            }
+            if failed > 0 {
+                bail!(
+                    "Repository check failed for {}/{} jobs",
+                    failed,
+                    checked.len()
+                );
+            }
+            println!("Repository check successfull");
+        }
+        Commands::Stats { job } => {
+            let mut shown: Vec<&Job> = match job {
+                Some(jobname) => {
+                    let Some(job) = jobs.get(jobname) else {
+                        bail!("No job named '{}' found!", jobname);
+                    };
+                    vec![job]
+                }
+                None => jobs.values().filter(|job| job.enabled()).collect(),
+            };
+            shown.sort_unstable_by_key(|job| job.name());
+            let (mut total_size, mut total_files, mut total_raw) = (0, 0, 0);
+            let mut failed = 0;
+            for job in shown.iter() {
+                let stats = job
+                    .stats("restore-size")
+                    .and_then(|restore| Ok((restore, job.stats("raw-data")?)));
+                match stats {
+                    Ok((restore, raw)) => {
+                        println!(
+                            "[{}]\t{} snapshots, {} in {} files, {} raw repository size",
+                            job.name(),
+                            restore.snapshots_count,
+                            format_size(restore.total_size),
+                            restore.total_file_count,
+                            format_size(raw.total_size)
+                        );
+                        total_size += restore.total_size;
+                        total_files += restore.total_file_count;
+                        total_raw += raw.total_size;
+                    }
+                    Err(CommandError::NotInitialized) => {
+                        println!("[{}]\tRepository not initialized.", job.name())
+                    }
+                    Err(e) => {
+                        eprintln!("[{}]\tFailed to get stats: {}", job.name(), e);
+                        failed += 1;
+                    }
+                }
+            }
+            if shown.len() > 1 {
+                println!(
+                    "Total: {} in {} files, {} raw repository size",
+                    format_size(total_size),
+                    total_files,
+                    format_size(total_raw)
+                );
+            }
+            if failed > 0 {
+                bail!("Failed to get stats for {}/{} jobs", failed, shown.len());
+            }
+        }
+        Commands::Diff {
+            job,
+            from,
+            to,
+            full,
+            top,
+        } => {
+            let Some(job) = jobs.get(job) else {
+                bail!("No job named '{}' found!", job);
+            };
+            let (from, to) = match (from, to) {
+                (Some(from), Some(to)) => (from.clone(), to.clone()),
+                (from, _) => {
+                    let mut snapshots = job.snapshots(Some(2))?;
+                    // multiple snapshot groups return the latest of each
+                    snapshots.sort_by_key(|snapshot| snapshot.time);
+                    let mut ids = snapshots.into_iter().rev().map(|snapshot| snapshot.id);
+                    let latest = ids.next();
+                    match (from.clone().or_else(|| ids.next()), latest) {
+                        (Some(from), Some(to)) => (from, to),
+                        _ => bail!(
+                            "Job '{}' has less than two snapshots to compare!",
+                            job.name()
+                        ),
+                    }
+                }
+            };
+            let (changes, stats) = job.diff(&from, &to)?;
+            let count = |modifiers: &[&str]| {
+                changes
+                    .iter()
+                    .filter(|change| modifiers.contains(&change.modifier.as_str()))
+                    .count()
+            };
+            println!("[{}]\tChanges from {} to {}", job.name(), from, to);
+            if *full || job.verbosity() > 0 {
+                for change in changes.iter() {
+                    println!("{}    {}", change.modifier, change.path);
+                }
+            }
+            println!(
+                "{} added, {} removed, {} modified, {} metadata only",
+                count(&["+"]),
+                count(&["-"]),
+                count(&["M", "T"]),
+                count(&["U"])
+            );
+            println!(
+                "{} added, {} removed",
+                format_size(stats.added.bytes),
+                format_size(stats.removed.bytes)
+            );
+            let added: Vec<&str> = changes
+                .iter()
+                .filter(|change| change.modifier == "+")
+                .map(|change| change.path.as_str())
+                .collect();
+            if *top > 0 && !added.is_empty() {
+                let sizes = job.file_sizes(&to)?;
+                let mut largest: Vec<(usize, &str)> = added
+                    .into_iter()
+                    .filter_map(|path| sizes.get(path).map(|size| (*size, path)))
+                    .collect();
+                largest.sort_unstable_by(|a, b| b.cmp(a));
+                if !largest.is_empty() {
+                    println!("Largest added files:");
+                }
+                for (size, path) in largest.into_iter().take(*top) {
+                    println!("{:>10}  {}", format_size(size), path);
+                }
+            }
+        }
+        Commands::Ls {
+            job,
+            snapshot,
+            path,
+        } => {
+            let Some(job) = jobs.get(job) else {
+                bail!("No job named '{}' found!", job);
+            };
+            job.ls(snapshot, path.as_deref(), |node| {
+                let size = match node.kind.as_str() {
+                    "file" => format_size(node.size),
+                    _ => String::from("-"),
+                };
+                let mtime = node
+                    .mtime
+                    .map(|mtime| {
+                        format!(
+                            "{} {:02}:{:02}:{:02}",
+                            mtime.date(),
+                            mtime.hour(),
+                            mtime.minute(),
+                            mtime.second()
+                        )
+                    })
+                    .unwrap_or_default();
+                let kind = node.kind.chars().next().unwrap_or('?');
+                println!("{} {:>10}  {:19}  {}", kind, size, mtime, node.path);
+            })?;
+        }
+        Commands::Mount { job, target } => {
+            let Some(job) = jobs.get(job) else {
+                bail!("No job named '{}' found!", job);
+            };
+            job.mount(target)?;
+        }
+        Commands::RotateKey { job, new_key_file } => {
+            let Some(job) = jobs.get(job) else {
+                bail!("No job named '{}' found!", job);
+            };
+            job.rotate_key(new_key_file)?;
+        }
+        Commands::History { job, limit, json } => {
+            history::print_history(&defaults, job, *limit, *json)?;
+        }
+        Commands::Status { json } => {
+            State::open(&defaults)?.print_status(&jobs, *json)?;
+        }
+        Commands::GenerateConfig { .. } => unreachable!("handled before loading the config"),
+        Commands::Daemon { run_missed } => {
             // update last_run for each job
             if jobs.is_empty() {
                 bail!("No backup jobs configured!");
             }
-            println!("Loading job snapshots");
+            let mut metrics = init_metrics(&defaults, jobs.values());
+            let mut state = State::load_logged(&defaults);
+            for job in jobs.values().filter(|job| !job.enabled()) {
+                log_println!(defaults, "[{}]\tJob disabled, skipping.", job.name());
+            }
+            jobs.retain(|_, job| job.enabled());
+            if jobs.is_empty() {
+                bail!("All backup jobs are disabled!");
+            }
+            log_println!(defaults, "Loading job snapshots");
             let mut jobs: Vec<_> = jobs
                 .into_values()
                 .map(|v| {
-                    let _ = v.snapshots(Some(1));
+                    if let Err(e) = v.snapshots(Some(1)) {
+                        // fall back to the persisted state if the repository is unreachable
+                        match state.last_run(v.name()) {
+                            Some(last_run) if e != CommandError::NotInitialized => {
+                                log_eprintln!(
+                                    defaults,
+                                    "[{}]\tFailed to load snapshots, using persisted last run {}: {}",
+                                    v.name(),
+                                    last_run,
+                                    e
+                                );
+                                v.restore_last_run(last_run);
+                            }
+                            _ => (),
+                        }
+                    }
                     v
                 })
                 .collect();
 
-            println!("Entering daemon mode");
+            if *run_missed {
+                let now = defaults.now();
+                for job in jobs.iter_mut() {
+                    let next_run = match state.next_run(job.name()) {
+                        Some(next_run) => next_run,
+                        // never run jobs start right away in the loop
+                        None if job.last_run().is_some() => job.next_run()?,
+                        None => continue,
+                    };
+                    if next_run < now {
+                        log_println!(
+                            defaults,
+                            "[{}]\tMissed backup expected at {}, running now",
+                            job.name(),
+                            next_run
+                        );
+                        backup_notify(job, &defaults, &mut metrics, &mut state)
+                            .wrap_err_with(|| format!("[{}] backup failed", job.name()))?;
+                        scheduled_check(job, &defaults, &mut state);
+                    }
+                }
+            }
+
+            log_println!(defaults, "Entering daemon mode");
+            let started = defaults.now();
+            let sleep = |duration| {
+                std::thread::sleep(duration);
+                heartbeat(&defaults);
+            };
             loop {
-                jobs.sort_unstable_by(|a, b| b.next_run().unwrap().cmp(&a.next_run().unwrap()));
+                heartbeat(&defaults);
+                // earliest start first, jobs waiting for their period don't block others
+                let offset = defaults.now().offset();
+                jobs.sort_by_cached_key(|job| {
+                    std::cmp::Reverse(next_task(job, &state, started, offset).0)
+                });
 
                 if let Some(mut job) = jobs.pop() {
-                    let now = OffsetDateTime::now_local().into_diagnostic()?;
-                    let sleep_time = job.next_run()? - now;
-                    // job interval
-                    if sleep_time.is_positive() {
-                        if defaults.verbose > 0 {
-                            println!("Waiting for cooldown time of job [{}]", job.name());
-                        }
-                        std::thread::sleep(sleep_time.try_into().into_diagnostic()?);
-                    }
-                    // backup window
-                    if let Some(period) = &defaults.period {
-                        let now = OffsetDateTime::now_local().into_diagnostic()?;
-                        if let Some(duration) =
-                            calc_period_sleep(period.backup_start_time, period.backup_end_time, now)
-                        {
-                            if defaults.verbose > 0 {
-                                println!("Waiting for backup start time");
-                            }
-                            std::thread::sleep(duration.try_into().into_diagnostic()?);
+                    // runs between backups, never concurrently to one of the same job
+                    if let (prune_at, Task::Prune) = next_task(&job, &state, started, offset) {
+                        if job.verbosity() > 0 {
+                            log_println!(defaults, "Waiting for prune of job [{}]", job.name());
                         }
+                        sleep_chunked(
+                            || {
+                                let now = defaults.now();
+                                let sleep_time = prune_at - now;
+                                match sleep_time.is_positive() {
+                                    true => Ok(Some(sleep_time.try_into().into_diagnostic()?)),
+                                    false => Ok(None),
+                                }
+                            },
+                            sleep,
+                        )?;
+                        prune_notify(&job, &defaults, &mut state);
+                        jobs.push(job);
+                        continue;
                     }
-                    match job.backup() {
-                        Ok(_) => (),
-                        Err(e) => {
-                            eprintln!("[{}]\tFailed to backup.", job.name());
-                            return Err(e);
-                        }
+                    // job interval
+                    if job.verbosity() > 0 && job.next_run()? > defaults.now() {
+                        log_println!(
+                            defaults,
+                            "Waiting for cooldown time of job [{}]",
+                            job.name()
+                        );
                     }
-                    // refresh last update time before adding the job back to the list
-                    if let Err(e) = job.update_last_run() {
-                        eprintln!(
-                            "[{}]\t Failed to refresh last update run! {}",
-                            job.name(),
-                            e
+                    sleep_chunked(
+                        || {
+                            let now = defaults.now();
+                            let sleep_time = job.next_run()? - now;
+                            match sleep_time.is_positive() {
+                                true => Ok(Some(sleep_time.try_into().into_diagnostic()?)),
+                                false => Ok(None),
+                            }
+                        },
+                        sleep,
+                    )?;
+                    // backup window
+                    if let Some(period) = job.period() {
+                        let now = defaults.now();
+                        let waiting = calc_period_sleep(
+                            period.backup_start_time,
+                            period.backup_end_time,
+                            now,
                         );
+                        if job.verbosity() > 0 && waiting.is_some() {
+                            log_println!(defaults, "Waiting for backup start time");
+                        }
+                        sleep_chunked(
+                            || {
+                                let now = defaults.now();
+                                Ok(calc_period_sleep(
+                                    period.backup_start_time,
+                                    period.backup_end_time,
+                                    now,
+                                ))
+                            },
+                            sleep,
+                        )?;
                     }
+                    backup_notify(&mut job, &defaults, &mut metrics, &mut state)
+                        .wrap_err_with(|| format!("[{}] backup failed", job.name()))?;
+                    scheduled_check(&job, &defaults, &mut state);
 
                     jobs.push(job);
                 }
@@ -243,27 +1020,375 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn read_config() -> Result<Conf> {
-    let file = File::open("config.toml").into_diagnostic()?;
-    #[cfg(not(target_os = "windows"))]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let mt = file.metadata().into_diagnostic()?;
-        let mode = mt.permissions().mode();
-        if mode & 0o007 != 0 {
-            bail!("Config file is world readable, aborting!");
+/// Write the current time to the heartbeat file, if configured.
+///
+/// Written atomically via a temporary file, errors are only logged.
+fn heartbeat(defaults: &Global) {
+    let Some(path) = &defaults.heartbeat_file else {
+        return;
+    };
+    let now = defaults
+        .now()
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap_or_default();
+    let tmp_path = path.with_extension("tmp");
+    let res = std::fs::write(&tmp_path, format!("{}\n", now))
+        .and_then(|_| std::fs::rename(&tmp_path, path));
+    if let Err(e) = res {
+        log_eprintln!(
+            defaults,
+            "Failed to write heartbeat file {}: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+/// Run backup of job, send notifications, record metrics and persist the state
+fn backup_notify(
+    job: &mut Job,
+    defaults: &Global,
+    metrics: &mut Option<Metrics>,
+    state: &mut State,
+) -> Result<BackupSummary> {
+    let start = Instant::now();
+    let res = job.backup();
+    let duration = start.elapsed();
+    if res.is_ok() {
+        // refresh last update time
+        if let Err(e) = job.update_last_run() {
+            log_eprintln!(
+                defaults,
+                "[{}]\t Failed to refresh last update run! {}",
+                job.name(),
+                e
+            );
+        }
+    }
+    state.record_logged(job, &res);
+    history::record_logged(defaults, job, &res);
+    notify::job_finished(defaults, job, &res, duration);
+    if let Some(metrics) = metrics {
+        metrics.record(job, &res, duration);
+        metrics.write_logged();
+    }
+    if let Ok(summary) = &res {
+        copy_notify(job, summary, defaults, state);
+    }
+    res
+}
+
+/// Copy the new snapshot to the `copy_to` repository of the job, if configured.
+///
+/// Failures are reported separately and don't fail the backup.
+fn copy_notify(job: &Job, summary: &BackupSummary, defaults: &Global, state: &mut State) {
+    if !job.has_copy_to() {
+        return;
+    }
+    let start = Instant::now();
+    let res = job.copy_snapshot(&summary.snapshot_id);
+    state.record_copy_logged(job, &res);
+    if let Err(e) = res {
+        log_eprintln!(
+            defaults,
+            "[{}]\tCopy to secondary repository failed, the backup itself succeeded: {:?}",
+            job.name(),
+            e
+        );
+        notify::task_failed(defaults, job, Task::Copy, e, start.elapsed());
+    }
+}
+
+/// Run scheduled prune of job, notify on failure and persist the state
+///
+/// Failures are only logged, they don't stop the daemon.
+fn prune_notify(job: &Job, defaults: &Global, state: &mut State) {
+    let start = Instant::now();
+    let res = job.prune();
+    state.record_prune_logged(job, &res);
+    if let Err(e) = res {
+        log_eprintln!(
+            defaults,
+            "[{}]\tScheduled prune failed: {:?}",
+            job.name(),
+            e
+        );
+        notify::task_failed(defaults, job, Task::Prune, e, start.elapsed());
+    }
+}
+
+/// Next task of a job in daemon mode, the earlier one of its backup and prune
+fn next_task(
+    job: &Job,
+    state: &State,
+    started: OffsetDateTime,
+    offset: UtcOffset,
+) -> (OffsetDateTime, Task) {
+    let backup = next_start(job.next_run().unwrap(), job.period(), offset);
+    match next_prune(job, state, started, offset) {
+        Some(prune) if prune < backup => (prune, Task::Prune),
+        _ => (backup, Task::Backup),
+    }
+}
+
+/// Next scheduled prune of a job, jobs that were never pruned are scheduled from `since`
+fn next_prune(
+    job: &Job,
+    state: &State,
+    since: OffsetDateTime,
+    offset: UtcOffset,
+) -> Option<OffsetDateTime> {
+    let last_prune = state.last_prune(job.name()).map(|v| v.to_offset(offset));
+    job.prune_schedule()?
+        .next(last_prune, since.to_offset(offset))
+}
+
+/// Run the periodic repository check of a job if it is due, failures are only logged
+fn scheduled_check(job: &Job, defaults: &Global, state: &mut State) {
+    let Some(interval) = job.check_interval() else {
+        return;
+    };
+    let now = OffsetDateTime::now_utc();
+    if !check_due(state.last_check(job.name()), interval, now) {
+        return;
+    }
+    let res = job.check(None);
+    if let Err(e) = &res {
+        log_eprintln!(
+            defaults,
+            "[{}]\tScheduled repository check failed: {:?}",
+            job.name(),
+            e
+        );
+    }
+    state.record_check_logged(job, &res);
+}
+
+/// Whether a repository check is due, never checked repositories are due immediately
+fn check_due(
+    last_check: Option<OffsetDateTime>,
+    interval: time::Duration,
+    now: OffsetDateTime,
+) -> bool {
+    last_check.is_none_or(|last_check| now - last_check >= interval)
+}
+
+/// Validate a snapshot timestamp for `restic backup --time`
+fn parse_snapshot_time(value: &str) -> std::result::Result<String, String> {
+    let format = time::format_description::parse_borrowed::<2>(
+        "[year]-[month]-[day] [hour]:[minute]:[second]",
+    )
+    .expect("invalid snapshot time format");
+    time::PrimitiveDateTime::parse(value, &format)
+        .map(|_| value.to_owned())
+        .map_err(|e| format!("expected 'YYYY-MM-DD HH:MM:SS', {e}"))
+}
+
+/// Names of all jobs matching the glob `pattern`, sorted
+///
+/// Errors if no job matches.
+fn matching_jobs<'a>(
+    names: impl Iterator<Item = &'a String>,
+    pattern: &str,
+) -> Result<Vec<String>> {
+    let mut matches: Vec<String> = names
+        .filter(|name| glob_match(pattern, name))
+        .cloned()
+        .collect();
+    if matches.is_empty() {
+        bail!("No job matching '{}' found!", pattern);
+    }
+    matches.sort();
+    Ok(matches)
+}
+
+/// Next expected run of a job, from the persisted state or the latest snapshot.
+///
+/// Jobs without any known run are due now.
+fn expected_run(job: &Job, state: &State) -> Result<OffsetDateTime> {
+    match state.last_run(job.name()) {
+        Some(last_run) => job.restore_last_run(last_run),
+        None => match job.update_last_run() {
+            Ok(()) | Err(CommandError::NotInitialized) => (),
+            Err(e) => eprintln!(
+                "[{}]\tFailed to load snapshots, assuming it never ran: {}",
+                job.name(),
+                e
+            ),
+        },
+    }
+    job.next_run()
+}
+
+/// Split jobs into the `max` due ones with the oldest next run, in that order,
+/// the deferred rest of the due jobs and the ones not due at `now`
+fn most_overdue(
+    mut next_runs: Vec<(String, OffsetDateTime)>,
+    now: OffsetDateTime,
+    max: usize,
+) -> (Vec<String>, Vec<String>, Vec<String>) {
+    next_runs.sort_by(|(a_name, a), (b_name, b)| a.cmp(b).then_with(|| a_name.cmp(b_name)));
+    let (due, not_due): (Vec<_>, Vec<_>) = next_runs.into_iter().partition(|(_, v)| *v <= now);
+    let mut names: Vec<String> = due.into_iter().map(|(name, _)| name).collect();
+    let deferred = names.split_off(max.min(names.len()));
+    let not_due = not_due.into_iter().map(|(name, _)| name).collect();
+    (names, deferred, not_due)
+}
+
+/// Match `name` against a glob `pattern`, `*` matches any sequence and `?` any single character
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // position of the last `*` and the name position it currently covers
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // let the last `*` consume one more character
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Read newline separated, existing paths from stdin
+fn read_stdin_paths() -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for line in std::io::stdin().lines() {
+        let line = line
+            .into_diagnostic()
+            .wrap_err("Reading paths from stdin")?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
         }
+        let path = PathBuf::from(line);
+        if !path.exists() {
+            bail!("Path {} from stdin does not exist!", path.display());
+        }
+        paths.push(path);
+    }
+    if paths.is_empty() {
+        bail!("No paths to backup received from stdin!");
+    }
+    Ok(paths)
+}
+
+/// Setup metrics textfile if configured, writes the initial state
+fn init_metrics<'a>(defaults: &Global, jobs: impl Iterator<Item = &'a Job>) -> Option<Metrics> {
+    let metrics = Metrics::new(defaults.metrics_textfile.clone()?, jobs);
+    metrics.write_logged();
+    Some(metrics)
+}
+
+/// Configuration file to load, the first existing one of:
+/// the CLI value, `$BACKUPRS_CONFIG`, `./config.toml`, `$XDG_CONFIG_HOME/backuprs/config.toml`
+/// and `/etc/backuprs/config.toml`.
+fn find_config(cli: Option<PathBuf>) -> Result<PathBuf> {
+    // explicitly specified, has to exist
+    if let Some(path) = cli.or_else(|| std::env::var_os("BACKUPRS_CONFIG").map(PathBuf::from)) {
+        if !path.is_file() {
+            bail!("Configuration file {} does not exist!", path.display());
+        }
+        return Ok(path);
     }
-    let mut reader = BufReader::new(file);
+    let candidates = config_candidates(
+        std::env::var_os("XDG_CONFIG_HOME"),
+        std::env::var_os("HOME"),
+    );
+    match candidates.iter().find(|path| path.is_file()) {
+        Some(path) => Ok(path.clone()),
+        None => bail!(
+            "No configuration file found, searched {}",
+            candidates
+                .iter()
+                .map(|v| v.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Default configuration paths in order of precedence
+fn config_candidates(xdg_config_home: Option<OsString>, home: Option<OsString>) -> Vec<PathBuf> {
+    let mut candidates = vec![PathBuf::from("config.toml")];
+    // XDG spec: fallback to $HOME/.config for unset or empty values
+    let config_home = xdg_config_home
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home.map(|home| PathBuf::from(home).join(".config")));
+    if let Some(config_home) = config_home {
+        candidates.push(config_home.join("backuprs").join("config.toml"));
+    }
+    #[cfg(not(target_os = "windows"))]
+    candidates.push(PathBuf::from("/etc/backuprs/config.toml"));
+    candidates
+}
+
+fn read_config(path: &Path, allow_insecure: bool) -> Result<Conf> {
+    let file = File::open(path).into_diagnostic()?;
+    let access = permissions::config_access(&file, path)?;
+    let mut reader = BufReader::new(&file);
     let mut cfg = String::new();
     reader.read_to_string(&mut cfg).into_diagnostic()?;
 
-    let config: Conf = toml::from_str(&cfg).into_diagnostic()?;
+    let format = ConfigFormat::from_path(path);
+    let (config, warnings) = config_format::parse(format, &path.display().to_string(), &cfg)?;
+    for warning in warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    // checked after parsing, as the config can allow insecure permissions
+    permissions::check_config(
+        &access,
+        allow_insecure || config.global.allow_insecure_config,
+        config.global.strict_permissions,
+    )?;
     Ok(config)
 }
 
-fn check_restic(cfg: &Global) -> Result<()> {
-    let outp = Command::new(&cfg.restic_binary)
+/// Verify the global and job specific restic binaries, storing their versions
+fn check_restic(config: &mut Conf) -> Result<()> {
+    let global = &mut config.global;
+    global.restic_version = Some(restic_version(&global.restic_binary, global)?);
+    // each distinct binary is only run once
+    let mut versions: HashMap<PathBuf, ResticVersion> = HashMap::new();
+    for job in config.job.iter_mut() {
+        let Some(binary) = &job.restic_binary else {
+            continue;
+        };
+        let version = match versions.get(binary) {
+            Some(version) => *version,
+            None => {
+                let version = restic_version(binary, &config.global).wrap_err_with(|| {
+                    format!("[{}] Checking restic_binary {}", job.name, binary.display())
+                })?;
+                versions.insert(binary.clone(), version);
+                version
+            }
+        };
+        job.restic_version = Some(version);
+    }
+    Ok(())
+}
+
+/// Version of a restic binary, fails if it's older than `min_restic_version`
+fn restic_version(binary: &Path, cfg: &Global) -> Result<ResticVersion> {
+    let outp = Command::new(binary)
         .arg("version")
         // .arg("--json") // unsupported
         .output()
@@ -271,21 +1396,102 @@ fn check_restic(cfg: &Global) -> Result<()> {
         .wrap_err("Restic can't be started")?;
     if !outp.status.success() {
         bail!(
-            "Restic exited test with status code {:?}",
-            outp.status.code()
+            "Restic exited test with status code {:?}: {}",
+            outp.status.code(),
+            String::from_utf8_lossy(&outp.stderr),
         );
     }
-    if !outp.stdout.starts_with(b"restic") {
-        bail!(
-            "Restic binary returned invalid output: {} {}",
-            String::from_utf8_lossy(&outp.stdout),
+    let stdout = String::from_utf8_lossy(&outp.stdout);
+    match parse_restic_version(&stdout) {
+        Some(version) => {
+            if cfg.verbose > 1 {
+                println!("Found restic version {} at {}", version, binary.display());
+            }
+            let version: ResticVersion = version
+                .parse()
+                .map_err(|e| miette!("Restic binary returned an invalid version: {}", e))?;
+            if let Some(min) = &cfg.min_restic_version {
+                let min: ResticVersion = min.parse().map_err(|e| miette!("{}", e))?;
+                if version < min {
+                    bail!(
+                        "Restic version {} is older than 'min_restic_version' {}, update restic or lower the requirement!",
+                        version,
+                        min
+                    );
+                }
+            }
+            Ok(version)
+        }
+        None => bail!(
+            "Restic binary returned invalid output, no version found: {} {}",
+            stdout,
             String::from_utf8_lossy(&outp.stderr),
-        );
+        ),
     }
+}
 
+/// Find the version in `restic version` output.
+///
+/// Scans all lines for `restic <version>`, as wrapper scripts may print additional output.
+fn parse_restic_version(output: &str) -> Option<&str> {
+    output.lines().find_map(|line| {
+        line.split_whitespace()
+            .collect::<Vec<_>>()
+            .windows(2)
+            .find(|words| words[0] == "restic" && is_version(words[1]))
+            .map(|words| words[1])
+    })
+}
+
+/// Whether the input looks like a `major.minor.patch` version, patch may have a suffix
+fn is_version(input: &str) -> bool {
+    let mut parts = input.splitn(3, '.');
+    let numeric =
+        |v: Option<&str>| v.is_some_and(|v| !v.is_empty() && v.bytes().all(|c| c.is_ascii_digit()));
+    numeric(parts.next())
+        && numeric(parts.next())
+        && parts
+            .next()
+            .is_some_and(|v| v.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+/// Maximum time to sleep before re-evaluating the remaining time.
+///
+/// Picks up wall clock changes (NTP, manual) and time spent in suspend,
+/// which the monotonic sleep doesn't account for.
+const SLEEP_CHUNK: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Sleep until `remaining` returns None, in chunks of at most [SLEEP_CHUNK].
+fn sleep_chunked(
+    mut remaining: impl FnMut() -> Result<Option<std::time::Duration>>,
+    mut sleep: impl FnMut(std::time::Duration),
+) -> Result<()> {
+    while let Some(duration) = remaining()? {
+        if duration.is_zero() {
+            break;
+        }
+        sleep(duration.min(SLEEP_CHUNK));
+    }
     Ok(())
 }
 
+/// Start time of a job, the next run moved into the backup period
+fn next_start(
+    next_run: OffsetDateTime,
+    period: Option<&BackupTimeRange>,
+    offset: UtcOffset,
+) -> OffsetDateTime {
+    let next_run = next_run.to_offset(offset);
+    match period {
+        Some(period) => {
+            next_run
+                + calc_period_sleep(period.backup_start_time, period.backup_end_time, next_run)
+                    .unwrap_or_default()
+        }
+        None => next_run,
+    }
+}
+
 fn calc_period_sleep(
     start: Time,
     end: Time,
@@ -323,6 +1529,180 @@ mod tests {
     use super::*;
     use time::Time;
 
+    #[test]
+    fn test_most_overdue() {
+        let now = OffsetDateTime::UNIX_EPOCH;
+        let next_runs = vec![
+            (String::from("b"), now),
+            (String::from("c"), now - time::Duration::hours(2)),
+            (String::from("a"), now),
+            (String::from("d"), now + time::Duration::hours(1)),
+        ];
+        let (run, deferred, not_due) = most_overdue(next_runs.clone(), now, 2);
+        assert_eq!(run, ["c", "a"]);
+        assert_eq!(deferred, ["b"]);
+        assert_eq!(not_due, ["d"]);
+        // jobs that aren't due never fill up the free slots
+        let (run, deferred, not_due) = most_overdue(next_runs, now, 10);
+        assert_eq!(run, ["c", "a", "b"]);
+        assert!(deferred.is_empty());
+        assert_eq!(not_due, ["d"]);
+    }
+
+    #[test]
+    fn test_config_candidates() {
+        let candidates = config_candidates(Some("/xdg".into()), Some("/home/user".into()));
+        assert_eq!(candidates[0], PathBuf::from("config.toml"));
+        assert_eq!(candidates[1], PathBuf::from("/xdg/backuprs/config.toml"));
+        // empty is treated as unset
+        let candidates = config_candidates(Some("".into()), Some("/home/user".into()));
+        assert_eq!(
+            candidates[1],
+            PathBuf::from("/home/user/.config/backuprs/config.toml")
+        );
+        #[cfg(not(target_os = "windows"))]
+        assert_eq!(
+            config_candidates(None, None),
+            [
+                PathBuf::from("config.toml"),
+                PathBuf::from("/etc/backuprs/config.toml")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sleep_chunked() {
+        // simulated clock, jumps back by 10 seconds after the first chunk
+        let now = std::cell::Cell::new(0u64);
+        let target = 150u64;
+        let mut jumped = false;
+        let mut slept = Vec::new();
+        sleep_chunked(
+            || Ok(target.checked_sub(now.get()).map(Duration::from_secs)),
+            |duration| {
+                slept.push(duration.as_secs());
+                now.set(now.get() + duration.as_secs());
+                if !jumped {
+                    jumped = true;
+                    now.set(now.get() - 10);
+                }
+            },
+        )
+        .unwrap();
+        assert_eq!(slept, vec![60, 60, 40]);
+    }
+
+    #[test]
+    fn test_parse_restic_version() {
+        assert_eq!(
+            Some("0.15.1"),
+            parse_restic_version("restic 0.15.1 compiled with go1.19.5 on linux/amd64\n")
+        );
+        // banner printed by a wrapper script
+        assert_eq!(
+            Some("0.16.0"),
+            parse_restic_version(
+                "Welcome to the backup host!\nUsing restic from /opt\nrestic 0.16.0 compiled with go1.21.0 on linux/amd64"
+            )
+        );
+        // localized wrapper output
+        assert_eq!(
+            Some("0.14.0-dev"),
+            parse_restic_version(
+                "Starte restic Wrapper...\nVersion: restic 0.14.0-dev (kompiliert) mit go1.18 auf windows/amd64"
+            )
+        );
+        assert_eq!(None, parse_restic_version("restic: Befehl nicht gefunden"));
+        assert_eq!(None, parse_restic_version("restic version unknown"));
+        assert_eq!(None, parse_restic_version(""));
+    }
+
+    #[test]
+    fn test_parse_snapshot_time() {
+        assert_eq!(
+            parse_snapshot_time("2019-05-01 13:45:00").as_deref(),
+            Ok("2019-05-01 13:45:00")
+        );
+        assert!(parse_snapshot_time("2019-05-01").is_err());
+        assert!(parse_snapshot_time("2019-13-01 13:45:00").is_err());
+        assert!(parse_snapshot_time("01.05.2019 13:45:00").is_err());
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("db-*", "db-postgres"));
+        assert!(glob_match("db-*", "db-"));
+        assert!(!glob_match("db-*", "web-db"));
+        assert!(glob_match("*-db", "web-db"));
+        assert!(glob_match("*db*", "web-db-main"));
+        assert!(glob_match("h?me", "home"));
+        assert!(!glob_match("h?me", "hme"));
+        assert!(glob_match("a*b*c", "axxbyybc"));
+        assert!(!glob_match("a*b*c", "axxbyyb"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("home", "home"));
+        assert!(!glob_match("home", "homes"));
+
+        let names = [
+            String::from("db-b"),
+            String::from("web"),
+            String::from("db-a"),
+        ];
+        assert_eq!(
+            matching_jobs(names.iter(), "db-*").unwrap(),
+            vec![String::from("db-a"), String::from("db-b")]
+        );
+        assert!(matching_jobs(names.iter(), "mail*").is_err());
+    }
+
+    #[test]
+    fn test_check_due() {
+        let now = OffsetDateTime::now_utc();
+        let interval = time::Duration::days(7);
+        assert!(check_due(None, interval, now));
+        assert!(!check_due(
+            Some(now - time::Duration::days(6)),
+            interval,
+            now
+        ));
+        assert!(check_due(
+            Some(now - time::Duration::days(7)),
+            interval,
+            now
+        ));
+    }
+
+    #[test]
+    fn test_next_start() {
+        let time = |h| Time::from_hms(h, 0, 0).unwrap();
+        let night = BackupTimeRange {
+            backup_start_time: time(22),
+            backup_end_time: time(4),
+        };
+        let day = BackupTimeRange {
+            backup_start_time: time(8),
+            backup_end_time: time(18),
+        };
+        let offset = UtcOffset::UTC;
+        let noon = OffsetDateTime::now_utc().replace_time(time(12));
+        // a job waiting for the night doesn't delay one that can run now
+        assert_eq!(
+            next_start(noon, Some(&night), offset),
+            noon.replace_time(time(22))
+        );
+        assert_eq!(next_start(noon, Some(&day), offset), noon);
+        assert_eq!(next_start(noon, None, offset), noon);
+        let evening = noon.replace_time(time(20));
+        assert_eq!(
+            next_start(evening, Some(&day), offset),
+            evening.replace_time(time(8)) + time::Duration::DAY
+        );
+        assert_eq!(
+            next_start(evening, Some(&night), offset),
+            evening.replace_time(time(22))
+        );
+    }
+
     #[test]
     fn test_calc_period() {
         // 05:00-07:00
@@ -0,0 +1,350 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use miette::{Context, IntoDiagnostic, Result};
+use time::OffsetDateTime;
+
+use crate::job::Job;
+use crate::models::BackupSummary;
+
+/// Prometheus metrics of all jobs, written as node_exporter textfile
+pub struct Metrics {
+    path: PathBuf,
+    /// Per job metrics, sorted for stable output
+    jobs: BTreeMap<String, JobMetrics>,
+}
+
+#[derive(Default)]
+struct JobMetrics {
+    backend: &'static str,
+    last_run: Option<i64>,
+    last_success: Option<i64>,
+    success: bool,
+    duration: f64,
+    summary: Option<SummaryMetrics>,
+}
+
+impl JobMetrics {
+    /// Summary numbers, created if missing
+    fn summary(&mut self) -> &mut SummaryMetrics {
+        self.summary.get_or_insert_with(Default::default)
+    }
+}
+
+/// Numbers of the last successful backup
+#[derive(Default)]
+struct SummaryMetrics {
+    data_added: usize,
+    files_new: usize,
+    files_changed: usize,
+    files_unmodified: usize,
+    total_bytes_processed: usize,
+}
+
+impl Metrics {
+    /// Metrics of all jobs, keeping the values of an existing metrics file
+    pub fn new<'a>(path: PathBuf, jobs: impl Iterator<Item = &'a Job>) -> Self {
+        let jobs = jobs
+            .map(|job| {
+                let metrics = JobMetrics {
+                    backend: job.backend_name(),
+                    ..Default::default()
+                };
+                (job.name().to_owned(), metrics)
+            })
+            .collect();
+        let mut metrics = Self { path, jobs };
+        match std::fs::read_to_string(&metrics.path) {
+            Ok(content) => metrics.merge(&content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => eprintln!(
+                "Failed to read metrics file {}, starting empty: {}",
+                metrics.path.display(),
+                e
+            ),
+        }
+        metrics
+    }
+
+    /// Take over the values of a previously written metrics file.
+    ///
+    /// Otherwise jobs that don't run in this invocation would lose their last results.
+    /// Jobs no longer configured are dropped.
+    fn merge(&mut self, content: &str) {
+        for line in content.lines() {
+            let Some((name, job, value)) = parse_series(line) else {
+                continue;
+            };
+            let Some(metrics) = self.jobs.get_mut(&job) else {
+                continue;
+            };
+            match name {
+                "backuprs_job_last_run_timestamp_seconds" => metrics.last_run = Some(value as i64),
+                "backuprs_job_last_success_timestamp_seconds" => {
+                    metrics.last_success = Some(value as i64)
+                }
+                "backuprs_job_success" => metrics.success = value == 1.0,
+                "backuprs_job_duration_seconds" => metrics.duration = value,
+                "backuprs_job_data_added_bytes" => metrics.summary().data_added = value as usize,
+                "backuprs_job_processed_bytes" => {
+                    metrics.summary().total_bytes_processed = value as usize
+                }
+                "backuprs_job_files_new" => metrics.summary().files_new = value as usize,
+                "backuprs_job_files_changed" => metrics.summary().files_changed = value as usize,
+                "backuprs_job_files_unmodified" => {
+                    metrics.summary().files_unmodified = value as usize
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Record result of a finished job
+    pub fn record(&mut self, job: &Job, result: &Result<BackupSummary>, duration: Duration) {
+        let metrics = self.jobs.entry(job.name().to_owned()).or_default();
+        metrics.backend = job.backend_name();
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        metrics.last_run = Some(now);
+        metrics.duration = duration.as_secs_f64();
+        metrics.success = result.is_ok();
+        if let Ok(summary) = result {
+            metrics.last_success = Some(now);
+            metrics.summary = Some(SummaryMetrics {
+                data_added: summary.data_added,
+                files_new: summary.files_new,
+                files_changed: summary.files_changed,
+                files_unmodified: summary.files_unmodified,
+                total_bytes_processed: summary.total_bytes_processed,
+            });
+        }
+    }
+
+    /// Write metrics file atomically, errors are only logged.
+    pub fn write_logged(&self) {
+        if let Err(e) = self.write() {
+            eprintln!("Failed to write metrics file: {:?}", e);
+        }
+    }
+
+    /// Write metrics file atomically, via temporary file and rename.
+    ///
+    /// The temporary file doesn't end in `.prom`, so node_exporter ignores it.
+    pub fn write(&self) -> Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        std::fs::write(&tmp_path, self.render())
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Writing metrics to {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, &self.path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Moving metrics file to {}", self.path.display()))?;
+        Ok(())
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        header(&mut out, "backuprs_job_info", "Configured backup jobs");
+        for (name, job) in self.jobs.iter() {
+            let _ = writeln!(
+                out,
+                "backuprs_job_info{{job=\"{}\",backend=\"{}\"}} 1",
+                escape(name),
+                job.backend
+            );
+        }
+        self.series(
+            &mut out,
+            "backuprs_job_last_run_timestamp_seconds",
+            "Time of the last finished backup run",
+            |job| job.last_run.map(|v| v as f64),
+        );
+        self.series(
+            &mut out,
+            "backuprs_job_last_success_timestamp_seconds",
+            "Time of the last successful backup run",
+            |job| job.last_success.map(|v| v as f64),
+        );
+        self.series(
+            &mut out,
+            "backuprs_job_success",
+            "Whether the last backup run succeeded",
+            |job| job.last_run.map(|_| job.success as u8 as f64),
+        );
+        self.series(
+            &mut out,
+            "backuprs_job_duration_seconds",
+            "Duration of the last backup run",
+            |job| job.last_run.map(|_| job.duration),
+        );
+        self.series(
+            &mut out,
+            "backuprs_job_data_added_bytes",
+            "Data added to the repository by the last successful backup",
+            |job| job.summary.as_ref().map(|s| s.data_added as f64),
+        );
+        self.series(
+            &mut out,
+            "backuprs_job_processed_bytes",
+            "Bytes processed by the last successful backup",
+            |job| job.summary.as_ref().map(|s| s.total_bytes_processed as f64),
+        );
+        self.series(
+            &mut out,
+            "backuprs_job_files_new",
+            "New files of the last successful backup",
+            |job| job.summary.as_ref().map(|s| s.files_new as f64),
+        );
+        self.series(
+            &mut out,
+            "backuprs_job_files_changed",
+            "Changed files of the last successful backup",
+            |job| job.summary.as_ref().map(|s| s.files_changed as f64),
+        );
+        self.series(
+            &mut out,
+            "backuprs_job_files_unmodified",
+            "Unmodified files of the last successful backup",
+            |job| job.summary.as_ref().map(|s| s.files_unmodified as f64),
+        );
+        out
+    }
+
+    /// Render one gauge with a value per job, skips jobs without value
+    fn series(
+        &self,
+        out: &mut String,
+        name: &str,
+        help: &str,
+        value: impl Fn(&JobMetrics) -> Option<f64>,
+    ) {
+        header(out, name, help);
+        for (job_name, job) in self.jobs.iter() {
+            if let Some(value) = value(job) {
+                let _ = writeln!(out, "{name}{{job=\"{}\"}} {value}", escape(job_name));
+            }
+        }
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} gauge");
+}
+
+/// Name, job label and value of a series line written by [Metrics::render]
+fn parse_series(line: &str) -> Option<(&str, String, f64)> {
+    let (name, rest) = line.split_once("{job=\"")?;
+    let mut job = String::new();
+    let mut chars = rest.char_indices();
+    let end = loop {
+        match chars.next()? {
+            (_, '\\') => match chars.next()?.1 {
+                'n' => job.push('\n'),
+                c => job.push(c),
+            },
+            (i, '"') => break i,
+            (_, c) => job.push(c),
+        }
+    };
+    // only series with the job as single label
+    let value = rest[end..].strip_prefix("\"} ")?;
+    Some((name, job, value.trim().parse().ok()?))
+}
+
+/// Escape label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut metrics = Metrics {
+            path: PathBuf::new(),
+            jobs: BTreeMap::new(),
+        };
+        metrics.jobs.insert(
+            String::from("a\"b"),
+            JobMetrics {
+                backend: "s3",
+                ..Default::default()
+            },
+        );
+        metrics.jobs.insert(
+            String::from("job"),
+            JobMetrics {
+                backend: "rest",
+                last_run: Some(100),
+                success: false,
+                duration: 1.5,
+                ..Default::default()
+            },
+        );
+        let out = metrics.render();
+        assert!(out.contains("backuprs_job_info{job=\"a\\\"b\",backend=\"s3\"} 1\n"));
+        assert!(out.contains("backuprs_job_info{job=\"job\",backend=\"rest\"} 1\n"));
+        assert!(out.contains("backuprs_job_last_run_timestamp_seconds{job=\"job\"} 100\n"));
+        assert!(out.contains("backuprs_job_success{job=\"job\"} 0\n"));
+        assert!(out.contains("backuprs_job_duration_seconds{job=\"job\"} 1.5\n"));
+        // no series for jobs that never ran
+        assert!(!out.contains("backuprs_job_success{job=\"a"));
+        assert!(!out.contains("backuprs_job_data_added_bytes{"));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut previous = Metrics {
+            path: PathBuf::new(),
+            jobs: BTreeMap::new(),
+        };
+        for name in ["a\"b\\c", "removed"] {
+            previous.jobs.insert(
+                name.to_owned(),
+                JobMetrics {
+                    backend: "rest",
+                    last_run: Some(200),
+                    last_success: Some(100),
+                    success: false,
+                    duration: 2.5,
+                    summary: Some(SummaryMetrics {
+                        data_added: 1024,
+                        files_new: 3,
+                        ..Default::default()
+                    }),
+                },
+            );
+        }
+        let mut metrics = Metrics {
+            path: PathBuf::new(),
+            jobs: BTreeMap::new(),
+        };
+        for name in ["a\"b\\c", "new"] {
+            metrics.jobs.insert(
+                name.to_owned(),
+                JobMetrics {
+                    backend: "s3",
+                    ..Default::default()
+                },
+            );
+        }
+        metrics.merge(&previous.render());
+        let job = &metrics.jobs["a\"b\\c"];
+        assert_eq!(job.backend, "s3");
+        assert_eq!((job.last_run, job.last_success), (Some(200), Some(100)));
+        assert!(!job.success);
+        assert_eq!(job.duration, 2.5);
+        let summary = job.summary.as_ref().unwrap();
+        assert_eq!((summary.data_added, summary.files_new), (1024, 3));
+        assert!(metrics.jobs["new"].last_run.is_none());
+        assert!(!metrics.jobs.contains_key("removed"));
+    }
+}
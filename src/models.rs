@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 use time::OffsetDateTime;
 
@@ -10,8 +10,34 @@ pub struct Snapshot {
     pub time: OffsetDateTime,
     pub paths: Vec<String>,
     pub hostname: String,
+    /// Omitted by restic if empty
+    #[serde(default)]
     pub username: String,
     pub id: String,
+    #[serde(default)]
+    pub short_id: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Snapshot this one is based on
+    pub parent: Option<String>,
+    /// Restic version that created the snapshot, since restic 0.17
+    pub program_version: Option<String>,
+}
+
+impl Snapshot {
+    /// Short ID as shown by restic
+    pub fn short_id(&self) -> &str {
+        match self.short_id.is_empty() {
+            true => self.id.get(..8).unwrap_or(&self.id),
+            false => &self.short_id,
+        }
+    }
+}
+
+/// Snapshots of one group, for `--group-by`
+#[derive(Debug, Deserialize)]
+pub struct SnapshotGroup {
+    pub snapshots: Snapshots,
 }
 
 #[derive(Debug, Deserialize)]
@@ -23,6 +49,64 @@ pub enum BackupMessage {
     Status(BackupStatus),
     #[serde(rename = "summary")]
     Summary(BackupSummary),
+    #[serde(rename = "error")]
+    Error(BackupError),
+    /// Fatal error, restic exits afterwards
+    #[serde(rename = "exit_error")]
+    ExitError(ExitError),
+}
+
+/// Reason restic exited with, since restic 0.17
+#[derive(Debug, Deserialize)]
+pub struct ExitError {
+    #[serde(default)]
+    pub code: i32,
+    #[serde(default)]
+    pub message: String,
+}
+
+/// File that couldn't be backed up, for example due to missing permissions
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BackupError {
+    #[serde(default)]
+    pub item: String,
+    /// Phase of the backup, for example "archival"
+    #[serde(default)]
+    pub during: String,
+    #[serde(default, rename = "error", with = "error_message")]
+    pub message: String,
+}
+
+/// restic nests the message as `"error": {"message": ".."}`
+mod error_message {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Deserialize, Serialize, Default)]
+    struct ErrorMessage {
+        #[serde(default)]
+        message: String,
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+        Ok(ErrorMessage::deserialize(deserializer)?.message)
+    }
+
+    pub fn serialize<S: Serializer>(message: &str, serializer: S) -> Result<S::Ok, S::Error> {
+        ErrorMessage {
+            message: message.to_owned(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl Display for BackupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "\"{}\" during {}: {}",
+            self.item, self.during, self.message
+        )
+    }
 }
 
 /// For some reason restic outputs 2 different kinds of normal status.
@@ -58,6 +142,8 @@ pub struct BackupStatusIntermediate {
     pub total_bytes: usize,
     #[serde(default)]
     pub bytes_done: usize,
+    /// Missing until restic can estimate it
+    pub seconds_remaining: Option<u64>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -72,8 +158,83 @@ pub struct BackupVerboseStatus {
     pub total_files: usize,
 }
 
-/// Returned from restic after a successfull backup
+/// Summary of `restic check`, JSON output requires restic 0.17+
+#[derive(Debug, Deserialize)]
+pub struct CheckSummary {
+    pub message_type: String,
+    pub num_errors: usize,
+}
+
+/// Returned from `restic cat config`
+#[derive(Debug, Deserialize)]
+pub struct RepoConfig {
+    pub version: u32,
+    pub id: String,
+}
+
+/// Returned from `restic stats`, the meaning of the values depends on the mode
+#[derive(Debug, Deserialize)]
+pub struct RepoStats {
+    pub total_size: usize,
+    /// Only set for the `restore-size` mode
+    #[serde(default)]
+    pub total_file_count: usize,
+    #[serde(default)]
+    pub snapshots_count: usize,
+}
+
+/// Line of `restic diff --json`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "message_type")]
+pub enum DiffMessage {
+    #[serde(rename = "change")]
+    Change(DiffChange),
+    #[serde(rename = "statistics")]
+    Statistics(DiffStatistics),
+}
+
+/// Changed path, the modifier is `+` for added, `-` for removed,
+/// `M` for modified content, `T` for a changed type and `U` for changed metadata
 #[derive(Debug, Deserialize)]
+pub struct DiffChange {
+    pub path: String,
+    pub modifier: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiffStatistics {
+    pub added: DiffCounts,
+    pub removed: DiffCounts,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiffCounts {
+    pub bytes: usize,
+}
+
+/// Node of `restic ls --json`
+#[derive(Debug, Deserialize)]
+pub struct LsNode {
+    /// `file`, `dir`, `symlink` or others
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub path: String,
+    /// Only set for files
+    #[serde(default)]
+    pub size: usize,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub mtime: Option<OffsetDateTime>,
+}
+
+/// Entry of `restic key list`
+#[derive(Debug, Deserialize)]
+pub struct KeyInfo {
+    pub current: bool,
+    pub id: String,
+}
+
+/// Returned from restic after a successfull backup
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct BackupSummary {
     // pub message_type":"summary
     pub files_new: usize,
@@ -89,26 +250,238 @@ pub struct BackupSummary {
     pub total_bytes_processed: usize,
     pub total_duration: f32,
     pub snapshot_id: String,
+    /// Files that couldn't be backed up, collected from the error messages
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<BackupError>,
+}
+
+impl BackupSummary {
+    /// Whether the snapshot differs from the previous one
+    pub fn has_changes(&self) -> bool {
+        self.files_new + self.files_changed + self.data_added > 0
+    }
 }
 
 impl Display for BackupSummary {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let (added_unit, added) = format_size(self.data_added);
-        f.write_fmt(format_args!("took {}s, {added} {added_unit} added, {} new files, {} changed files, {} unchanged files",
-        self.total_duration,self.files_new,self.files_changed,self.files_unmodified))
+        f.write_fmt(format_args!(
+            "took {}, {} added, {} new files, {} changed files, {} unchanged files",
+            format_duration(time::Duration::seconds_f32(self.total_duration)),
+            format_size(self.data_added),
+            self.files_new,
+            self.files_changed,
+            self.files_unmodified
+        ))?;
+        if !self.errors.is_empty() {
+            write!(f, ", {} errors reported by restic", self.errors.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// Format a size with one decimal in its largest 1024 based unit, for example `1.9 GiB`
+pub fn format_size(bytes: usize) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next in &UNITS[1..] {
+        // would be rounded up to 1024.0
+        if size < 1023.95 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    format!("{size:.1} {unit}")
+}
+
+/// Format duration in its two largest units, for example `2h 15m`, `3d` or `42s`.
+///
+/// Durations below 10 seconds keep one decimal, `0.1s`.
+pub fn format_duration(duration: time::Duration) -> String {
+    const UNITS: [(i64, &str); 4] = [(86400, "d"), (3600, "h"), (60, "m"), (1, "s")];
+    let duration = duration.abs();
+    let secs = duration.whole_seconds();
+    if secs < 10 {
+        return format!("{:.1}s", duration.as_seconds_f64());
     }
+    let index = UNITS
+        .iter()
+        .position(|(size, _)| secs >= *size)
+        .unwrap_or(UNITS.len() - 1);
+    let (size, unit) = UNITS[index];
+    let mut out = format!("{}{}", secs / size, unit);
+    if let Some((next_size, next_unit)) = UNITS.get(index + 1) {
+        let rest = secs % size / next_size;
+        if rest > 0 {
+            out.push_str(&format!(" {}{}", rest, next_unit));
+        }
+    }
+    out
 }
 
-pub const fn format_size(bytes: usize) -> (&'static str, usize) {
-    if bytes > 2 << 40 {
-        ("TiB", bytes / (2 << 40))
-    } else if bytes > 2 << 30 {
-        ("GiB", bytes / (2 << 30))
-    } else if bytes > 2 << 20 {
-        ("MiB", bytes / (2 << 20))
-    } else if bytes > 2 << 10 {
-        ("KiB", bytes / (2 << 10))
+/// Time relative to `now`, for example `in 4h 10m`, `2d 3h ago` or `now`
+pub fn format_relative(now: OffsetDateTime, time: OffsetDateTime) -> String {
+    let diff = time - now;
+    if diff.whole_seconds().abs() < 60 {
+        String::from("now")
+    } else if diff.is_positive() {
+        format!("in {}", format_duration(diff))
     } else {
-        ("B", bytes)
+        format!("{} ago", format_duration(diff))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1024), "1.0 KiB");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size((1 << 20) - 1), "1.0 MiB");
+        assert_eq!(format_size(1 << 20), "1.0 MiB");
+        assert_eq!(format_size(3 << 29), "1.5 GiB");
+        assert_eq!(format_size((19 << 30) / 10), "1.9 GiB");
+        assert_eq!(format_size(1 << 40), "1.0 TiB");
+        assert_eq!(format_size(3 << 39), "1.5 TiB");
+    }
+
+    #[test]
+    fn test_format_duration() {
+        let secs = |v: f64| format_duration(time::Duration::seconds_f64(v));
+        assert_eq!(secs(0.0), "0.0s");
+        assert_eq!(secs(0.07), "0.1s");
+        assert_eq!(secs(9.5), "9.5s");
+        assert_eq!(secs(42.7), "42s");
+        assert_eq!(secs(60.0), "1m");
+        assert_eq!(secs(200.0), "3m 20s");
+        assert_eq!(secs(3600.0), "1h");
+        assert_eq!(secs(8100.0), "2h 15m");
+        assert_eq!(secs(8159.0), "2h 15m");
+        assert_eq!(secs(86400.0 * 3.0), "3d");
+        assert_eq!(secs(86400.0 * 3.0 + 4.0 * 3600.0), "3d 4h");
+        assert_eq!(secs(-200.0), "3m 20s");
+
+        let now = OffsetDateTime::UNIX_EPOCH;
+        let at = |secs| now + time::Duration::seconds(secs);
+        assert_eq!(format_relative(now, at(30)), "now");
+        assert_eq!(format_relative(now, at(8100)), "in 2h 15m");
+        assert_eq!(format_relative(now, at(-86400 * 2)), "2d ago");
+    }
+
+    #[test]
+    fn test_backup_error() {
+        let line = r#"{"message_type":"error","error":{"message":"open /root/secret: permission denied"},"during":"archival","item":"/root/secret"}"#;
+        let msg: BackupMessage = serde_json::from_str(line).unwrap();
+        let BackupMessage::Error(e) = msg else {
+            panic!("unexpected message {msg:?}");
+        };
+        assert_eq!(e.item, "/root/secret");
+        assert_eq!(e.message, "open /root/secret: permission denied");
+        assert_eq!(
+            e.to_string(),
+            "\"/root/secret\" during archival: open /root/secret: permission denied"
+        );
+        let line = r#"{"message_type":"exit_error","code":10,"message":"Fatal: repository does not exist: unable to open config file"}"#;
+        let BackupMessage::ExitError(e) = serde_json::from_str(line).unwrap() else {
+            panic!("expected exit error");
+        };
+        assert_eq!(e.code, 10);
+        assert!(e.message.starts_with("Fatal: repository does not exist"));
+        // older restic versions serialize the error as empty object
+        let line = r#"{"message_type":"error","error":{},"during":"scan","item":"/root"}"#;
+        assert!(matches!(
+            serde_json::from_str(line).unwrap(),
+            BackupMessage::Error(BackupError { ref message, .. }) if message.is_empty()
+        ));
+    }
+
+    #[test]
+    fn test_diff_message() {
+        let line = r#"{"message_type":"change","path":"/srv/data/new.bin","modifier":"+"}"#;
+        let msg: DiffMessage = serde_json::from_str(line).unwrap();
+        assert!(
+            matches!(msg, DiffMessage::Change(DiffChange { ref path, ref modifier }) if path == "/srv/data/new.bin" && modifier == "+")
+        );
+        let line = r#"{"message_type":"statistics","source_snapshot":"a1b2c3d4","target_snapshot":"e5f6a7b8","changed_files":3,"added":{"files":2,"dirs":1,"others":0,"data_blobs":4,"tree_blobs":2,"bytes":4096},"removed":{"files":1,"dirs":0,"others":0,"data_blobs":1,"tree_blobs":1,"bytes":1024}}"#;
+        let DiffMessage::Statistics(stats) = serde_json::from_str(line).unwrap() else {
+            panic!("expected statistics");
+        };
+        assert_eq!(stats.added.bytes, 4096);
+        assert_eq!(stats.removed.bytes, 1024);
+    }
+
+    #[test]
+    fn test_ls_node() {
+        let line = r#"{"name":"data.bin","type":"file","path":"/srv/data.bin","uid":0,"gid":0,"size":2048,"mode":420,"permissions":"-rw-r--r--","mtime":"2023-03-01T12:00:00.123456789+01:00","atime":"2023-03-01T12:00:00+01:00","ctime":"2023-03-01T12:00:00+01:00","struct_type":"node"}"#;
+        let node: LsNode = serde_json::from_str(line).unwrap();
+        assert_eq!(node.path, "/srv/data.bin");
+        assert_eq!(node.kind, "file");
+        assert_eq!(node.size, 2048);
+        assert_eq!(node.mtime.unwrap().hour(), 12);
+        let line = r#"{"name":"srv","type":"dir","path":"/srv","mtime":"2023-03-01T12:00:00Z","struct_type":"node"}"#;
+        let node: LsNode = serde_json::from_str(line).unwrap();
+        assert_eq!(node.size, 0);
+        // the first line describes the snapshot
+        let line = r#"{"time":"2023-03-01T12:00:00Z","tree":"abcd","paths":["/srv"],"hostname":"host","id":"abcd","short_id":"abcd","struct_type":"snapshot"}"#;
+        assert!(serde_json::from_str::<LsNode>(line).is_err());
+    }
+
+    #[test]
+    fn test_snapshots() {
+        // restic 0.14, without tags or parent
+        let v0_14 = r#"[{"time":"2022-09-01T02:00:00.123456789+02:00","tree":"4f2a","paths":["/srv"],"hostname":"host","username":"root","uid":0,"gid":0,"id":"8e4b2f1c9d0a7b6e5f4c3d2a1b0c9d8e7f6a5b4c3d2e1f0a9b8c7d6e5f4a3b2c","short_id":"8e4b2f1c"}]"#;
+        let snapshots: Snapshots = serde_json::from_str(v0_14).unwrap();
+        assert_eq!(snapshots[0].short_id(), "8e4b2f1c");
+        assert!(snapshots[0].tags.is_empty());
+        assert_eq!(snapshots[0].parent, None);
+
+        // restic 0.15, with tags and parent
+        let v0_15 = r#"[{"time":"2023-02-01T02:00:00.5+01:00","parent":"8e4b2f1c9d0a7b6e5f4c3d2a1b0c9d8e7f6a5b4c3d2e1f0a9b8c7d6e5f4a3b2c","tree":"5a3b","paths":["/srv","/etc"],"hostname":"host","username":"root","uid":0,"gid":0,"tags":["backuprs","daily"],"id":"1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b1c2d3e4f5a6b7c8d9e0f1a2b","short_id":"1a2b3c4d"}]"#;
+        let snapshots: Snapshots = serde_json::from_str(v0_15).unwrap();
+        assert_eq!(snapshots[0].tags, ["backuprs", "daily"]);
+        assert_eq!(
+            snapshots[0].parent.as_deref(),
+            Some("8e4b2f1c9d0a7b6e5f4c3d2a1b0c9d8e7f6a5b4c3d2e1f0a9b8c7d6e5f4a3b2c")
+        );
+
+        // restic 0.16, with excludes and without username
+        let v0_16 = r#"[{"time":"2023-08-01T02:00:00Z","tree":"6b4c","paths":["/srv"],"hostname":"host","excludes":["*.tmp"],"tags":["backuprs"],"id":"9f8e7d6c5b4a39281706f5e4d3c2b1a09f8e7d6c5b4a39281706f5e4d3c2b1a0","short_id":"9f8e7d6c"}]"#;
+        let snapshots: Snapshots = serde_json::from_str(v0_16).unwrap();
+        assert!(snapshots[0].username.is_empty());
+        assert_eq!(snapshots[0].program_version, None);
+
+        // restic 0.17 adds the program version and a summary
+        let v0_17 = r#"[{"time":"2024-08-01T02:00:00Z","tree":"7c5d","paths":["/srv"],"hostname":"host","username":"root","program_version":"restic 0.17.0","summary":{"files_new":1},"id":"0a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f9"}]"#;
+        let snapshots: Snapshots = serde_json::from_str(v0_17).unwrap();
+        assert_eq!(
+            snapshots[0].program_version.as_deref(),
+            Some("restic 0.17.0")
+        );
+        // derived from the id if missing
+        assert_eq!(snapshots[0].short_id(), "0a1b2c3d");
+    }
+
+    #[test]
+    fn test_repo_stats() {
+        let restore: RepoStats = serde_json::from_str(
+            r#"{"total_size":5368709120,"total_file_count":1200,"snapshots_count":14}"#,
+        )
+        .unwrap();
+        assert_eq!(restore.total_file_count, 1200);
+        assert_eq!(restore.snapshots_count, 14);
+        let raw: RepoStats = serde_json::from_str(
+            r#"{"total_size":1073741824,"total_uncompressed_size":2147483648,"compression_ratio":2,"total_blob_count":800,"snapshots_count":14}"#,
+        )
+        .unwrap();
+        assert_eq!(raw.total_size, 1073741824);
+        assert_eq!(raw.total_file_count, 0);
     }
 }
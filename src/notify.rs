@@ -0,0 +1,411 @@
+use std::time::Duration;
+
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use miette::{Context, IntoDiagnostic, Report, Result};
+use serde_json::json;
+
+use crate::config::{
+    EmailNotify, Global, GotifyNotify, NotifyChannel, NotifyEvent, NtfyNotify, WebhookNotify,
+};
+use crate::error::CommandError;
+use crate::job::Job;
+use crate::models::BackupSummary;
+
+/// Timeout for requests of push notifications and healthchecks
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Task of a job to notify about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Task {
+    Backup,
+    /// Scheduled `restic forget --prune`
+    Prune,
+    /// Copy of a new snapshot to the `copy_to` repository
+    Copy,
+}
+
+impl Task {
+    fn name(self) -> &'static str {
+        match self {
+            Task::Backup => "backup",
+            Task::Prune => "prune",
+            Task::Copy => "copy",
+        }
+    }
+}
+
+/// Finished job to notify about
+pub struct JobResult<'a> {
+    pub job: &'a Job,
+    pub task: Task,
+    pub result: &'a Result<BackupSummary>,
+    pub duration: Duration,
+}
+
+impl JobResult<'_> {
+    fn event(&self) -> NotifyEvent {
+        match self.result {
+            Ok(_) => NotifyEvent::Success,
+            Err(_) => NotifyEvent::Failure,
+        }
+    }
+
+    /// Short message title
+    fn title(&self) -> String {
+        let task = self.task.name();
+        match self.result {
+            Ok(summary) if !summary.errors.is_empty() => {
+                format!("[{}] {task} completed with warnings", self.job.name())
+            }
+            Ok(_) => format!("[{}] {task} finished", self.job.name()),
+            Err(e) if CommandError::is_repository_full(e) => {
+                format!("[{}] {task} failed, repository full", self.job.name())
+            }
+            Err(_) => format!("[{}] {task} failed", self.job.name()),
+        }
+    }
+
+    /// Message body with summary or error chain
+    fn message(&self) -> String {
+        let finished = self.job.now();
+        match self.result {
+            Ok(summary) => format!(
+                "Backup job '{}' finished at {finished}, {summary}",
+                self.job.name()
+            ),
+            Err(e) if self.task != Task::Backup => format!(
+                "{} of job '{}' failed after {:.1}s at {finished}.\n\n{}",
+                match self.task {
+                    Task::Prune => "Pruning the repository",
+                    _ => "Copying the new snapshot",
+                },
+                self.job.name(),
+                self.duration.as_secs_f64(),
+                error_chain(e)
+            ),
+            Err(e) => format!(
+                "Backup job '{}' failed after {:.1}s at {finished}.\n\n{}",
+                self.job.name(),
+                self.duration.as_secs_f64(),
+                error_chain(e)
+            ),
+        }
+    }
+}
+
+/// Notification channel
+pub trait Notifier {
+    fn channel(&self) -> NotifyChannel;
+
+    /// Channel name for logging
+    fn name(&self) -> &'static str {
+        self.channel().name()
+    }
+
+    /// Events to notify on, unless overridden by the job
+    fn events(&self) -> &[NotifyEvent];
+
+    /// Send notification for a finished job
+    fn notify(&self, result: &JobResult) -> Result<()>;
+
+    /// Send a test message
+    fn test(&self) -> Result<()>;
+}
+
+/// All configured notification channels
+fn notifiers(global: &Global) -> Vec<&dyn Notifier> {
+    let notify = &global.notify;
+    let mut notifiers: Vec<&dyn Notifier> = Vec::new();
+    if let Some(v) = &notify.email {
+        notifiers.push(v);
+    }
+    if let Some(v) = &notify.webhook {
+        notifiers.push(v);
+    }
+    if let Some(v) = &notify.ntfy {
+        notifiers.push(v);
+    }
+    if let Some(v) = &notify.gotify {
+        notifiers.push(v);
+    }
+    notifiers
+}
+
+/// Notify about a finished backup job via all configured channels.
+///
+/// Errors during notification are only logged, they never abort the backup flow.
+pub fn job_finished(
+    global: &Global,
+    job: &Job,
+    result: &Result<BackupSummary>,
+    duration: Duration,
+) {
+    let result = JobResult {
+        job,
+        task: Task::Backup,
+        result,
+        duration,
+    };
+    notify_all(global, &result);
+}
+
+/// Notify about a failed task other than the backup via all configured channels.
+///
+/// Errors during notification are only logged.
+pub fn task_failed(global: &Global, job: &Job, task: Task, error: Report, duration: Duration) {
+    let result = JobResult {
+        job,
+        task,
+        result: &Err(error),
+        duration,
+    };
+    notify_all(global, &result);
+}
+
+/// Send notification via all channels enabled for the job and event
+fn notify_all(global: &Global, result: &JobResult) {
+    if global.no_notify {
+        return;
+    }
+    let job = result.job;
+    let event = result.event();
+    for notifier in notifiers(global) {
+        if !job.notify_channel(notifier.channel()) {
+            continue;
+        }
+        if !job
+            .notify_on()
+            .unwrap_or(notifier.events())
+            .contains(&event)
+        {
+            continue;
+        }
+        if let Err(e) = notifier.notify(result) {
+            eprintln!(
+                "[{}]\tFailed to send {} notification: {:?}",
+                job.name(),
+                notifier.name(),
+                e
+            );
+        }
+    }
+}
+
+/// Ping healthcheck endpoint, `suffix` is appended to the URL (`/start`, `/fail`).
+///
+/// Errors are only logged.
+pub fn healthcheck(job: &str, url: &str, suffix: &str, body: String) {
+    let url = format!("{}{suffix}", url.trim_end_matches('/'));
+    if let Err(e) = ureq::post(&url).timeout(HTTP_TIMEOUT).send_string(&body) {
+        eprintln!("[{job}]\tFailed to ping healthcheck: {}", e);
+    }
+}
+
+/// Send a test notification via all configured channels.
+pub fn test(global: &Global) -> Result<()> {
+    let notifiers = notifiers(global);
+    if notifiers.is_empty() {
+        println!("No notification channels configured.");
+    }
+    for notifier in notifiers {
+        notifier
+            .test()
+            .wrap_err_with(|| format!("Sending test {} notification", notifier.name()))?;
+        println!("Test {} notification sent.", notifier.name());
+    }
+    Ok(())
+}
+
+/// Render error with all of its causes, one per line
+pub fn error_chain(error: &Report) -> String {
+    error
+        .chain()
+        .enumerate()
+        .map(|(i, e)| match i {
+            0 => e.to_string(),
+            _ => format!("  caused by: {e}"),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+const TEST_TITLE: &str = "backuprs test notification";
+const TEST_MESSAGE: &str = "This is a test notification sent by backuprs.";
+
+impl Notifier for EmailNotify {
+    fn channel(&self) -> NotifyChannel {
+        NotifyChannel::Email
+    }
+
+    fn events(&self) -> &[NotifyEvent] {
+        &self.on
+    }
+
+    fn notify(&self, result: &JobResult) -> Result<()> {
+        self.send(&result.title(), result.message())
+    }
+
+    fn test(&self) -> Result<()> {
+        self.send(TEST_TITLE, String::from(TEST_MESSAGE))
+    }
+}
+
+impl EmailNotify {
+    fn send(&self, subject: &str, body: String) -> Result<()> {
+        let mut builder = Message::builder()
+            .from(self.from.parse().into_diagnostic()?)
+            .subject(subject);
+        for to in self.to.iter() {
+            builder = builder.to(to.parse().into_diagnostic()?);
+        }
+        let message = builder.body(body).into_diagnostic()?;
+
+        let mut transport = match self.starttls {
+            true => SmtpTransport::starttls_relay(&self.smtp_host),
+            false => SmtpTransport::relay(&self.smtp_host),
+        }
+        .into_diagnostic()?;
+        if let Some(port) = self.smtp_port {
+            transport = transport.port(port);
+        }
+        if let (Some(user), Some(password)) = (&self.user, &self.password) {
+            transport = transport.credentials(Credentials::new(user.clone(), password.clone()));
+        }
+        transport
+            .build()
+            .send(&message)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Sending mail via {}", self.smtp_host))?;
+        Ok(())
+    }
+}
+
+impl Notifier for WebhookNotify {
+    fn channel(&self) -> NotifyChannel {
+        NotifyChannel::Webhook
+    }
+
+    fn events(&self) -> &[NotifyEvent] {
+        &self.on
+    }
+
+    fn notify(&self, result: &JobResult) -> Result<()> {
+        let event = result.event();
+        let url = result.job.webhook_url().unwrap_or(&self.url);
+        let (summary, error) = match result.result {
+            Ok(summary) => (Some(summary), None),
+            Err(e) => (None, Some(error_chain(e))),
+        };
+        let body = json!({
+            "job": result.job.name(),
+            "task": result.task.name(),
+            "status": event,
+            "duration": result.duration.as_secs_f64(),
+            "summary": summary,
+            "error": error,
+        });
+        self.send(url, body)
+    }
+
+    fn test(&self) -> Result<()> {
+        let body = json!({
+            "job": null,
+            "status": "test",
+            "duration": 0,
+            "summary": null,
+            "error": null,
+        });
+        self.send(&self.url, body)
+    }
+}
+
+impl WebhookNotify {
+    fn send(&self, url: &str, body: serde_json::Value) -> Result<()> {
+        let mut request = ureq::post(url).timeout(Duration::from_secs(self.timeout));
+        for (key, value) in self.headers.iter() {
+            request = request.set(key, value);
+        }
+        request
+            .send_json(body)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("POST to {url}"))?;
+        Ok(())
+    }
+}
+
+impl Notifier for NtfyNotify {
+    fn channel(&self) -> NotifyChannel {
+        NotifyChannel::Ntfy
+    }
+
+    fn events(&self) -> &[NotifyEvent] {
+        &self.on
+    }
+
+    fn notify(&self, result: &JobResult) -> Result<()> {
+        let event = result.event();
+        self.send(&result.title(), &result.message(), self.priority.get(event))
+    }
+
+    fn test(&self) -> Result<()> {
+        self.send(TEST_TITLE, TEST_MESSAGE, None)
+    }
+}
+
+impl NtfyNotify {
+    fn send(&self, title: &str, message: &str, priority: Option<u8>) -> Result<()> {
+        let url = format!("{}/{}", self.server.trim_end_matches('/'), self.topic);
+        let mut request = ureq::post(&url).timeout(HTTP_TIMEOUT).set("Title", title);
+        if let Some(priority) = priority {
+            request = request.set("Priority", &priority.to_string());
+        }
+        if let Some(token) = &self.token {
+            request = request.set("Authorization", &format!("Bearer {token}"));
+        }
+        request
+            .send_string(message)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Publishing to ntfy topic '{}'", self.topic))?;
+        Ok(())
+    }
+}
+
+impl Notifier for GotifyNotify {
+    fn channel(&self) -> NotifyChannel {
+        NotifyChannel::Gotify
+    }
+
+    fn events(&self) -> &[NotifyEvent] {
+        &self.on
+    }
+
+    fn notify(&self, result: &JobResult) -> Result<()> {
+        let event = result.event();
+        self.send(&result.title(), &result.message(), self.priority.get(event))
+    }
+
+    fn test(&self) -> Result<()> {
+        self.send(TEST_TITLE, TEST_MESSAGE, None)
+    }
+}
+
+impl GotifyNotify {
+    fn send(&self, title: &str, message: &str, priority: Option<u8>) -> Result<()> {
+        let url = format!("{}/message", self.url.trim_end_matches('/'));
+        let mut body = json!({
+            "title": title,
+            "message": message,
+        });
+        if let Some(priority) = priority {
+            body["priority"] = priority.into();
+        }
+        ureq::post(&url)
+            .timeout(HTTP_TIMEOUT)
+            .set("X-Gotify-Key", &self.token)
+            .send_json(body)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Sending gotify message to {}", self.url))?;
+        Ok(())
+    }
+}
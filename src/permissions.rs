@@ -0,0 +1,375 @@
+//! Check that the config file, containing repository passwords, isn't readable by other users.
+use std::fs::File;
+use std::path::Path;
+
+use miette::{Context, Diagnostic, IntoDiagnostic, Result};
+use thiserror::Error;
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("{problem}")]
+#[diagnostic(code(config::insecure_permissions))]
+pub struct InsecureConfig {
+    problem: String,
+    #[help]
+    help: String,
+}
+
+/// Problem with the permissions of the config file
+#[derive(Debug)]
+pub struct Finding {
+    problem: String,
+    /// How to fix it
+    help: &'static str,
+    severity: Severity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    /// Fatal with `strict_permissions`, otherwise a warning
+    Strict,
+    /// Abort unless insecure configs are allowed
+    Fatal,
+}
+
+impl Finding {
+    fn new(severity: Severity, problem: String, help: &'static str) -> Self {
+        Self {
+            problem,
+            help,
+            severity,
+        }
+    }
+}
+
+/// Source of the permission problems of a file
+pub trait FileAccess {
+    /// All problems found, empty if the file is only accessible by trusted principals
+    fn findings(&self) -> Result<Vec<Finding>>;
+}
+
+/// Fail on the first fatal finding, returns all others as warnings.
+///
+/// `strict` makes [Severity::Strict] findings fatal, with `allow_insecure` fatal findings
+/// are also returned as warnings.
+pub fn verify(
+    access: &impl FileAccess,
+    allow_insecure: bool,
+    strict: bool,
+) -> Result<Vec<InsecureConfig>> {
+    let mut warnings = Vec::new();
+    for finding in access.findings()? {
+        let severe = match finding.severity {
+            Severity::Strict => strict,
+            Severity::Fatal => true,
+        };
+        let fatal = severe && !allow_insecure;
+        let err = InsecureConfig {
+            problem: finding.problem,
+            help: match severe {
+                true => format!(
+                    "{} Pass --allow-insecure-config or set allow_insecure_config to ignore this.",
+                    finding.help
+                ),
+                false => finding.help.to_owned(),
+            },
+        };
+        if fatal {
+            return Err(err.into());
+        }
+        warnings.push(err);
+    }
+    Ok(warnings)
+}
+
+/// Permission problems of the opened config file at `path`.
+///
+/// The metadata of the opened file is the one of the symlink target, if `path` is a symlink.
+pub fn config_access<'a>(file: &'a File, path: &Path) -> Result<impl FileAccess + 'a> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let meta = file.metadata().into_diagnostic()?;
+        // directories of the path itself and of the symlink target
+        let mut dirs: Vec<(std::path::PathBuf, u32)> = Vec::new();
+        for path in [std::path::absolute(path), path.canonicalize()] {
+            let path = path.into_diagnostic()?;
+            let Some(dir) = path.parent() else {
+                continue;
+            };
+            if dirs.iter().any(|(v, _)| v == dir) {
+                continue;
+            }
+            let mode = std::fs::metadata(dir)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Reading permissions of {}", dir.display()))?
+                .mode();
+            dirs.push((dir.to_owned(), mode));
+        }
+        Ok(UnixMeta {
+            mode: meta.mode(),
+            owner: meta.uid(),
+            // SAFETY: always successful, no memory access
+            user: unsafe { libc::geteuid() },
+            dirs,
+        })
+    }
+    #[cfg(windows)]
+    {
+        let _ = path;
+        Ok(WindowsAcl(file))
+    }
+}
+
+/// Verify the access, printing warnings for non-fatal findings
+pub fn check_config(access: &impl FileAccess, allow_insecure: bool, strict: bool) -> Result<()> {
+    for warning in verify(access, allow_insecure, strict)? {
+        eprintln!("Warning: {:?}", miette::Report::new(warning));
+    }
+    Ok(())
+}
+
+/// Unix file metadata and the executing user
+#[cfg(unix)]
+struct UnixMeta {
+    mode: u32,
+    /// uid of the file owner
+    owner: u32,
+    /// effective uid of the process
+    user: u32,
+    /// Containing directories and their mode
+    dirs: Vec<(std::path::PathBuf, u32)>,
+}
+
+#[cfg(unix)]
+impl FileAccess for UnixMeta {
+    fn findings(&self) -> Result<Vec<Finding>> {
+        let mut findings = Vec::new();
+        if self.owner != self.user && self.owner != 0 {
+            findings.push(Finding::new(
+                Severity::Fatal,
+                format!(
+                    "Config file is owned by uid {}, not by the executing user {} or root",
+                    self.owner, self.user
+                ),
+                "Change the owner via `chown <user> <config>`.",
+            ));
+        }
+        if self.mode & 0o007 != 0 {
+            findings.push(Finding::new(
+                Severity::Fatal,
+                String::from("Config file is world accessible"),
+                "Remove the access of other users via `chmod o= <config>`.",
+            ));
+        }
+        if self.mode & 0o070 != 0 {
+            findings.push(Finding::new(
+                Severity::Strict,
+                String::from("Config file is accessible by its group"),
+                "Fine for a dedicated backup group, otherwise remove it via `chmod g= <config>`.",
+            ));
+        }
+        for (dir, mode) in self.dirs.iter() {
+            // the sticky bit prevents replacing files of other users, like in /tmp
+            if mode & 0o002 != 0 && mode & 0o1000 == 0 {
+                findings.push(Finding::new(
+                    Severity::Fatal,
+                    format!(
+                        "Directory {} of the config file is world writable",
+                        dir.display()
+                    ),
+                    "Anyone could replace the config, remove the write access via `chmod o-w <dir>`.",
+                ));
+            }
+        }
+        Ok(findings)
+    }
+}
+
+/// DACL of a file, read access granted to Everyone, Users or Authenticated Users is fatal.
+///
+/// Deny entries aren't evaluated, so this errs on the side of reporting.
+#[cfg(windows)]
+struct WindowsAcl<'a>(&'a File);
+
+#[cfg(windows)]
+impl FileAccess for WindowsAcl<'_> {
+    fn findings(&self) -> Result<Vec<Finding>> {
+        use std::os::windows::io::AsRawHandle;
+        use std::ptr::null_mut;
+        use windows_sys::Win32::Foundation::{LocalFree, ERROR_SUCCESS};
+        use windows_sys::Win32::Security::Authorization::{GetSecurityInfo, SE_FILE_OBJECT};
+        use windows_sys::Win32::Security::{
+            CreateWellKnownSid, EqualSid, GetAce, WinAuthenticatedUserSid, WinBuiltinUsersSid,
+            WinWorldSid, ACCESS_ALLOWED_ACE, ACE_HEADER, ACL, DACL_SECURITY_INFORMATION,
+            INHERIT_ONLY_ACE, PSECURITY_DESCRIPTOR, PSID, SECURITY_MAX_SID_SIZE,
+            WELL_KNOWN_SID_TYPE,
+        };
+
+        const ACCESS_ALLOWED_ACE_TYPE: u8 = 0;
+        const FILE_READ_DATA: u32 = 0x0000_0001;
+        const GENERIC_ALL: u32 = 0x1000_0000;
+        const GENERIC_READ: u32 = 0x8000_0000;
+        const READ_ACCESS: u32 = FILE_READ_DATA | GENERIC_ALL | GENERIC_READ;
+
+        let untrusted: [(WELL_KNOWN_SID_TYPE, &str); 3] = [
+            (WinWorldSid, "Everyone"),
+            (WinBuiltinUsersSid, "Users"),
+            (WinAuthenticatedUserSid, "Authenticated Users"),
+        ];
+        let mut sids = Vec::with_capacity(untrusted.len());
+        for (kind, name) in untrusted {
+            let mut sid = [0u8; SECURITY_MAX_SID_SIZE as usize];
+            let mut size = sid.len() as u32;
+            // SAFETY: buffer of the maximum SID size, size passed along
+            let ok = unsafe {
+                CreateWellKnownSid(kind, null_mut(), sid.as_mut_ptr() as PSID, &mut size)
+            };
+            if ok == 0 {
+                return Err(std::io::Error::last_os_error()).into_diagnostic();
+            }
+            sids.push((sid, name));
+        }
+
+        let mut dacl: *mut ACL = null_mut();
+        let mut descriptor: PSECURITY_DESCRIPTOR = null_mut();
+        // SAFETY: valid file handle, descriptor is freed below
+        let res = unsafe {
+            GetSecurityInfo(
+                self.0.as_raw_handle(),
+                SE_FILE_OBJECT,
+                DACL_SECURITY_INFORMATION,
+                null_mut(),
+                null_mut(),
+                &mut dacl,
+                null_mut(),
+                &mut descriptor,
+            )
+        };
+        if res != ERROR_SUCCESS {
+            return Err(std::io::Error::from_raw_os_error(res as i32)).into_diagnostic();
+        }
+        let mut readers = Vec::new();
+        if dacl.is_null() {
+            // no DACL grants full access to everyone
+            readers.push(String::from("Everyone (no DACL)"));
+        } else {
+            // SAFETY: dacl points into the descriptor, which is alive until LocalFree
+            let count = unsafe { (*dacl).AceCount };
+            for i in 0..count as u32 {
+                let mut ace = null_mut();
+                // SAFETY: index is below AceCount
+                if unsafe { GetAce(dacl, i, &mut ace) } == 0 {
+                    continue;
+                }
+                // SAFETY: every ACE starts with a header
+                let header = unsafe { &*(ace as *const ACE_HEADER) };
+                if header.AceType != ACCESS_ALLOWED_ACE_TYPE
+                    || header.AceFlags as u32 & INHERIT_ONLY_ACE != 0
+                {
+                    continue;
+                }
+                // SAFETY: checked ACE type above
+                let allowed = unsafe { &*(ace as *const ACCESS_ALLOWED_ACE) };
+                if allowed.Mask & READ_ACCESS == 0 {
+                    continue;
+                }
+                let sid = &allowed.SidStart as *const u32 as PSID;
+                for (known, name) in sids.iter_mut() {
+                    // SAFETY: both are valid SIDs
+                    if unsafe { EqualSid(sid, known.as_mut_ptr() as PSID) } != 0
+                        && !readers.iter().any(|v| v == name)
+                    {
+                        readers.push(name.to_string());
+                    }
+                }
+            }
+        }
+        // SAFETY: allocated by GetSecurityInfo
+        unsafe { LocalFree(descriptor as _) };
+        Ok(readers
+            .into_iter()
+            .map(|reader| {
+                Finding::new(
+                    Severity::Fatal,
+                    format!("Config file is readable by {reader}"),
+                    "Remove the access of Everyone, Users and Authenticated Users in the security \
+                    properties of the file, or via `icacls <config> /inheritance:r /grant:r %USERNAME%:F`.",
+                )
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Findings(Vec<(&'static str, Severity)>);
+
+    impl FileAccess for Findings {
+        fn findings(&self) -> Result<Vec<Finding>> {
+            Ok(self
+                .0
+                .iter()
+                .map(|(problem, severity)| Finding::new(*severity, problem.to_string(), "Fix it."))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        assert!(verify(&Findings(vec![]), false, true).unwrap().is_empty());
+        assert!(verify(&Findings(vec![]), true, true).unwrap().is_empty());
+
+        let access = Findings(vec![
+            ("group", Severity::Strict),
+            ("world", Severity::Fatal),
+        ]);
+        let err = verify(&access, false, false).unwrap_err();
+        assert_eq!(err.to_string(), "world");
+        assert!(err
+            .help()
+            .unwrap()
+            .to_string()
+            .starts_with("Fix it. Pass --allow-insecure-config"));
+        let err = verify(&access, false, true).unwrap_err();
+        assert_eq!(err.to_string(), "group");
+
+        for strict in [false, true] {
+            let warnings = verify(&access, true, strict).unwrap();
+            let warnings: Vec<_> = warnings.iter().map(|v| v.to_string()).collect();
+            assert_eq!(warnings, ["group", "world"]);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_meta() {
+        use Severity::*;
+        let check = |mode, owner, user, dir_mode| {
+            UnixMeta {
+                mode,
+                owner,
+                user,
+                dirs: vec![("/etc/backuprs".into(), dir_mode)],
+            }
+            .findings()
+            .unwrap()
+            .iter()
+            .map(|v| v.severity)
+            .collect::<Vec<_>>()
+        };
+        // (mode, owner, user, directory mode) -> severity of each finding
+        assert!(check(0o600, 1000, 1000, 0o755).is_empty());
+        assert!(check(0o600, 0, 1000, 0o755).is_empty());
+        assert_eq!(check(0o640, 1000, 1000, 0o755), [Strict]);
+        assert_eq!(check(0o750, 0, 1000, 0o755), [Strict]);
+        assert_eq!(check(0o604, 1000, 1000, 0o755), [Fatal]);
+        assert_eq!(check(0o601, 0, 0, 0o755), [Fatal]);
+        assert_eq!(check(0o600, 1001, 1000, 0o755), [Fatal]);
+        assert_eq!(check(0o600, 1000, 0, 0o755), [Fatal]);
+        assert_eq!(check(0o664, 1001, 1000, 0o755), [Fatal, Fatal, Strict]);
+        assert_eq!(check(0o600, 1000, 1000, 0o777), [Fatal]);
+        assert!(check(0o600, 1000, 1000, 0o1777).is_empty());
+    }
+}
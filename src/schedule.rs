@@ -0,0 +1,234 @@
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime, Time};
+
+/// Schedule of a recurring task, an interval in days or a cron expression
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "ScheduleValue", into = "ScheduleValue")]
+pub enum Schedule {
+    Days(u64),
+    Cron(Cron),
+}
+
+/// Config representation of [Schedule]
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum ScheduleValue {
+    Days(u64),
+    Cron(String),
+}
+
+impl TryFrom<ScheduleValue> for Schedule {
+    type Error = String;
+
+    fn try_from(value: ScheduleValue) -> Result<Self, Self::Error> {
+        match value {
+            ScheduleValue::Days(0) => {
+                Err(String::from("schedule interval has to be at least 1 day"))
+            }
+            ScheduleValue::Days(days) => Ok(Schedule::Days(days)),
+            ScheduleValue::Cron(expr) => expr.parse().map(Schedule::Cron),
+        }
+    }
+}
+
+impl From<Schedule> for ScheduleValue {
+    fn from(value: Schedule) -> Self {
+        match value {
+            Schedule::Days(days) => ScheduleValue::Days(days),
+            Schedule::Cron(cron) => ScheduleValue::Cron(cron.expr),
+        }
+    }
+}
+
+impl Schedule {
+    /// Next run after the `last` one, `since` is used if the task never ran.
+    ///
+    /// Tasks on an interval that never ran are due at `since`.
+    /// Cron expressions are evaluated in the offset of the passed times.
+    /// None if a cron expression never matches, for example on February 31st.
+    pub fn next(
+        &self,
+        last: Option<OffsetDateTime>,
+        since: OffsetDateTime,
+    ) -> Option<OffsetDateTime> {
+        match self {
+            Schedule::Days(days) => Some(last.map_or(since, |v| v + Duration::days(*days as _))),
+            Schedule::Cron(cron) => cron.next_after(last.unwrap_or(since)),
+        }
+    }
+}
+
+impl std::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Schedule::Days(1) => write!(f, "daily"),
+            Schedule::Days(days) => write!(f, "every {days} days"),
+            Schedule::Cron(cron) => write!(f, "cron '{}'", cron.expr),
+        }
+    }
+}
+
+/// Cron expression of minute, hour, day of month, month and day of week.
+///
+/// Each field supports `*`, values, ranges `1-5`, lists `1,3` and steps `*/15` or `0-30/10`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    expr: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether days or weekdays are restricted, both restricted match either one
+    days_any: bool,
+    weekdays_any: bool,
+}
+
+impl std::str::FromStr for Cron {
+    type Err = String;
+
+    fn from_str(expr: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!(
+                "invalid cron expression '{expr}', expected 5 fields: minute hour day month weekday"
+            ));
+        };
+        let field = |value: &str, name: &str, min: u32, max: u32| {
+            parse_field(value, min, max)
+                .ok_or_else(|| format!("invalid {name} '{value}' in cron expression '{expr}'"))
+        };
+        let mut weekday_bits = field(weekdays, "weekday", 0, 7)?;
+        // sunday as 0 or 7
+        if weekday_bits & (1 << 7) != 0 {
+            weekday_bits |= 1;
+        }
+        Ok(Self {
+            expr: expr.to_owned(),
+            minutes: field(minutes, "minute", 0, 59)?,
+            hours: field(hours, "hour", 0, 23)?,
+            days: field(days, "day", 1, 31)?,
+            months: field(months, "month", 1, 12)?,
+            weekdays: weekday_bits,
+            days_any: days == "*",
+            weekdays_any: weekdays == "*",
+        })
+    }
+}
+
+/// Bitmask of all values matching a cron field
+fn parse_field(value: &str, min: u32, max: u32) -> Option<u64> {
+    let mut bits = 0u64;
+    for part in value.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().ok().filter(|v| *v > 0)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+                None => {
+                    let value = range.parse().ok()?;
+                    // `5/10` runs from 5 to the maximum
+                    (value, if part.contains('/') { max } else { value })
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        for v in (start..=end).step_by(step) {
+            bits |= 1 << v;
+        }
+    }
+    Some(bits)
+}
+
+impl Cron {
+    /// First matching minute after `time`
+    pub fn next_after(&self, time: OffsetDateTime) -> Option<OffsetDateTime> {
+        let matches = |bits: u64, v: u8| bits & (1 << v) != 0;
+        let mut next = time.replace_second(0).ok()?.replace_nanosecond(0).ok()? + Duration::MINUTE;
+        // every valid day matches at least once in 4 leap years
+        let limit = next + Duration::days(4 * 366);
+        while next < limit {
+            let day = matches(self.days, next.day());
+            let weekday = matches(self.weekdays, next.weekday().number_days_from_sunday());
+            let day = match (self.days_any, self.weekdays_any) {
+                (false, false) => day || weekday,
+                _ => day && weekday,
+            };
+            if !matches(self.months, next.month() as u8) || !day {
+                next = next.replace_time(Time::MIDNIGHT) + Duration::DAY;
+            } else if !matches(self.hours, next.hour()) {
+                next = next.replace_minute(0).ok()? + Duration::HOUR;
+            } else if !matches(self.minutes, next.minute()) {
+                next += Duration::MINUTE;
+            } else {
+                return Some(next);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn datetime(value: &str) -> OffsetDateTime {
+        let value = format!("{}:00Z", value.replace(' ', "T"));
+        OffsetDateTime::parse(&value, &time::format_description::well_known::Rfc3339).unwrap()
+    }
+
+    #[test]
+    fn test_cron() {
+        let cron: Cron = "30 3 * * *".parse().unwrap();
+        let now = datetime("2023-03-01 12:00");
+        assert_eq!(cron.next_after(now), Some(datetime("2023-03-02 03:30")));
+        assert_eq!(
+            cron.next_after(datetime("2023-03-02 03:30")),
+            Some(datetime("2023-03-03 03:30"))
+        );
+        // sundays, 2023-03-05 is a sunday
+        let cron: Cron = "0 4 * * 7".parse().unwrap();
+        assert_eq!(cron.next_after(now), Some(datetime("2023-03-05 04:00")));
+        // first of the month or mondays
+        let cron: Cron = "0 0 1 * 1".parse().unwrap();
+        assert_eq!(cron.next_after(now), Some(datetime("2023-03-06 00:00")));
+        let cron: Cron = "*/20 8-9 * 1,6 *".parse().unwrap();
+        assert_eq!(cron.next_after(now), Some(datetime("2023-06-01 08:00")));
+        assert_eq!(
+            cron.next_after(datetime("2023-06-01 09:40")),
+            Some(datetime("2023-06-02 08:00"))
+        );
+        let cron: Cron = "0 0 31 2 *".parse().unwrap();
+        assert_eq!(cron.next_after(now), None);
+
+        assert!("* * * *".parse::<Cron>().is_err());
+        assert!("60 * * * *".parse::<Cron>().is_err());
+        assert!("*/0 * * * *".parse::<Cron>().is_err());
+        assert!("5-1 * * * *".parse::<Cron>().is_err());
+    }
+
+    #[test]
+    fn test_schedule() {
+        let since = datetime("2023-03-01 12:00");
+        let days: Schedule = serde_json::from_str("7").unwrap();
+        assert_eq!(days.next(None, since), Some(since));
+        assert_eq!(
+            days.next(Some(datetime("2023-02-27 01:00")), since),
+            Some(datetime("2023-03-06 01:00"))
+        );
+        let cron: Schedule = serde_json::from_str("\"0 2 * * *\"").unwrap();
+        // missed runs are due immediately
+        assert_eq!(
+            cron.next(Some(datetime("2023-02-20 02:00")), since),
+            Some(datetime("2023-02-21 02:00"))
+        );
+        assert_eq!(cron.next(None, since), Some(datetime("2023-03-02 02:00")));
+        assert!(serde_json::from_str::<Schedule>("0").is_err());
+        assert!(serde_json::from_str::<Schedule>("\"daily\"").is_err());
+    }
+}
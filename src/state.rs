@@ -0,0 +1,402 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use miette::{bail, Context, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::config::Global;
+use crate::job::{Job, JobMap};
+use crate::models::{format_relative, format_size, BackupSummary};
+use crate::notify::error_chain;
+
+/// Current version of the state file format
+const STATE_VERSION: u32 = 1;
+const STATE_FILE: &str = "backuprs_state.json";
+
+/// Persisted job state, survives restarts
+#[derive(Debug, Serialize, Deserialize)]
+pub struct State {
+    version: u32,
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(default)]
+    pub jobs: BTreeMap<String, JobState>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct JobState {
+    /// Time of last snapshot
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub last_run: Option<OffsetDateTime>,
+    /// Expected next run at the time of writing
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub next_run: Option<OffsetDateTime>,
+    /// Time the last run finished, successful or not
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub finished: Option<OffsetDateTime>,
+    /// Whether the last run succeeded
+    pub success: Option<bool>,
+    /// Summary of the last run, if successful
+    pub summary: Option<BackupSummary>,
+    /// Error of the last run, if failed
+    pub error: Option<String>,
+    /// Time the last repository check finished
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub last_check: Option<OffsetDateTime>,
+    /// Error of the last repository check, if failed
+    #[serde(default)]
+    pub check_error: Option<String>,
+    /// Time the last scheduled prune finished
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub last_prune: Option<OffsetDateTime>,
+    /// Error of the last scheduled prune, if failed
+    #[serde(default)]
+    pub prune_error: Option<String>,
+    /// Error of the last copy to the `copy_to` repository, if failed
+    #[serde(default)]
+    pub copy_error: Option<String>,
+}
+
+impl State {
+    /// Load state file from the scratch_dir, logs errors and starts empty on failure.
+    pub fn load_logged(global: &Global) -> Self {
+        let path = global.scratch_dir.join(STATE_FILE);
+        match Self::load(&path) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("Failed to load state file, ignoring it: {:?}", e);
+                Self::new(path)
+            }
+        }
+    }
+
+    /// Load state file from the scratch_dir, empty if not yet existing.
+    pub fn open(global: &Global) -> Result<Self> {
+        Self::load(&global.scratch_dir.join(STATE_FILE))
+    }
+
+    fn new(path: PathBuf) -> Self {
+        Self {
+            version: STATE_VERSION,
+            path,
+            jobs: BTreeMap::new(),
+        }
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::new(path.to_owned()));
+        }
+        let data = std::fs::read(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Reading state file {}", path.display()))?;
+        let mut state: State = serde_json::from_slice(&data)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Parsing state file {}", path.display()))?;
+        if state.version != STATE_VERSION {
+            bail!(
+                "Unsupported state file version {}, expected {}",
+                state.version,
+                STATE_VERSION
+            );
+        }
+        state.path = path.to_owned();
+        Ok(state)
+    }
+
+    /// Persisted last run of a job
+    pub fn last_run(&self, job: &str) -> Option<OffsetDateTime> {
+        self.jobs.get(job).and_then(|v| v.last_run)
+    }
+
+    /// Persisted next run of a job, as expected after its last run
+    pub fn next_run(&self, job: &str) -> Option<OffsetDateTime> {
+        self.jobs.get(job).and_then(|v| v.next_run)
+    }
+
+    /// Update job entry with the result of a run and write the state file.
+    ///
+    /// Errors are only logged.
+    pub fn record_logged(&mut self, job: &Job, result: &Result<BackupSummary>) {
+        let entry = self.jobs.entry(job.name().to_owned()).or_default();
+        entry.last_run = job.last_run();
+        entry.next_run = job.next_run().ok();
+        entry.finished = Some(OffsetDateTime::now_utc());
+        entry.success = Some(result.is_ok());
+        match result {
+            Ok(summary) => {
+                entry.summary = Some(summary.clone());
+                entry.error = None;
+            }
+            Err(e) => {
+                entry.summary = None;
+                entry.error = Some(error_chain(e));
+            }
+        }
+        if let Err(e) = self.save() {
+            eprintln!("[{}]\tFailed to write state file: {:?}", job.name(), e);
+        }
+    }
+
+    /// Persisted time of the last repository check of a job
+    pub fn last_check(&self, job: &str) -> Option<OffsetDateTime> {
+        self.jobs.get(job).and_then(|v| v.last_check)
+    }
+
+    /// Update job entry with the result of a repository check and write the state file.
+    ///
+    /// Errors are only logged.
+    pub fn record_check_logged(&mut self, job: &Job, result: &Result<()>) {
+        let entry = self.jobs.entry(job.name().to_owned()).or_default();
+        entry.last_check = Some(OffsetDateTime::now_utc());
+        entry.check_error = result.as_ref().err().map(error_chain);
+        if let Err(e) = self.save() {
+            eprintln!("[{}]\tFailed to write state file: {:?}", job.name(), e);
+        }
+    }
+
+    /// Persisted time of the last scheduled prune of a job
+    pub fn last_prune(&self, job: &str) -> Option<OffsetDateTime> {
+        self.jobs.get(job).and_then(|v| v.last_prune)
+    }
+
+    /// Update job entry with the result of a scheduled prune and write the state file.
+    ///
+    /// Errors are only logged.
+    pub fn record_prune_logged(&mut self, job: &Job, result: &Result<()>) {
+        let entry = self.jobs.entry(job.name().to_owned()).or_default();
+        entry.last_prune = Some(OffsetDateTime::now_utc());
+        entry.prune_error = result.as_ref().err().map(error_chain);
+        if let Err(e) = self.save() {
+            eprintln!("[{}]\tFailed to write state file: {:?}", job.name(), e);
+        }
+    }
+
+    /// Update job entry with the result of a copy to the `copy_to` repository and write the state file.
+    ///
+    /// Errors are only logged.
+    pub fn record_copy_logged(&mut self, job: &Job, result: &Result<()>) {
+        let entry = self.jobs.entry(job.name().to_owned()).or_default();
+        entry.copy_error = result.as_ref().err().map(error_chain);
+        if let Err(e) = self.save() {
+            eprintln!("[{}]\tFailed to write state file: {:?}", job.name(), e);
+        }
+    }
+
+    /// Write state file atomically, via temporary file and rename.
+    fn save(&self) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self).into_diagnostic()?;
+        let tmp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, data)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Writing state file {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, &self.path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Moving state file to {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+/// Job entry for the status output
+#[derive(Serialize)]
+struct StatusEntry<'a> {
+    job: &'a str,
+    enabled: bool,
+    /// Job no longer in the configuration
+    stale: bool,
+    #[serde(with = "time::serde::rfc3339::option")]
+    last_run: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    finished: Option<OffsetDateTime>,
+    success: Option<bool>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    next_run: Option<OffsetDateTime>,
+    summary: Option<&'a BackupSummary>,
+    error: Option<&'a str>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    last_check: Option<OffsetDateTime>,
+    check_error: Option<&'a str>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    last_prune: Option<OffsetDateTime>,
+    prune_error: Option<&'a str>,
+    copy_error: Option<&'a str>,
+}
+
+impl State {
+    /// Print status of all configured and persisted jobs.
+    ///
+    /// Restores last_run of jobs from the state, to calculate their next run.
+    pub fn print_status(&self, jobs: &JobMap, json: bool) -> Result<()> {
+        let mut names: Vec<&str> = jobs
+            .keys()
+            .chain(self.jobs.keys())
+            .map(|v| v.as_str())
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+
+        let entries: Vec<StatusEntry> = names
+            .into_iter()
+            .map(|name| {
+                let state = self.jobs.get(name);
+                let job = jobs.get(name);
+                let last_run = state.and_then(|v| v.last_run);
+                let next_run = match job {
+                    Some(job) if job.enabled() => {
+                        if let Some(last_run) = last_run {
+                            job.restore_last_run(last_run);
+                        }
+                        job.next_run().ok()
+                    }
+                    _ => None,
+                };
+                StatusEntry {
+                    job: name,
+                    enabled: job.is_some_and(|v| v.enabled()),
+                    stale: job.is_none(),
+                    last_run,
+                    finished: state.and_then(|v| v.finished),
+                    success: state.and_then(|v| v.success),
+                    next_run,
+                    summary: state.and_then(|v| v.summary.as_ref()),
+                    error: state.and_then(|v| v.error.as_deref()),
+                    last_check: state.and_then(|v| v.last_check),
+                    check_error: state.and_then(|v| v.check_error.as_deref()),
+                    last_prune: state.and_then(|v| v.last_prune),
+                    prune_error: state.and_then(|v| v.prune_error.as_deref()),
+                    copy_error: state.and_then(|v| v.copy_error.as_deref()),
+                }
+            })
+            .collect();
+
+        if json {
+            let out = serde_json::to_string_pretty(&entries).into_diagnostic()?;
+            println!("{}", out);
+            return Ok(());
+        }
+
+        let now = OffsetDateTime::now_utc();
+        let width = entries
+            .iter()
+            .map(|v| v.job.len())
+            .max()
+            .unwrap_or(0)
+            .max(3);
+        println!(
+            "{:width$}  {:12}  {:7}  {:12}  ADDED",
+            "JOB", "LAST RUN", "RESULT", "NEXT RUN"
+        );
+        for entry in entries.iter() {
+            let last_run = entry
+                .finished
+                .map_or_else(|| String::from("never"), |v| format_relative(now, v));
+            let result = match entry.success {
+                Some(true) => "ok",
+                Some(false) => "failed",
+                None => "-",
+            };
+            let next_run = match (entry.stale, entry.enabled, entry.next_run) {
+                (true, _, _) => String::from("-"),
+                (false, false, _) => String::from("disabled"),
+                (false, true, Some(v)) => format_relative(now, v),
+                (false, true, None) => String::from("-"),
+            };
+            let added = entry
+                .summary
+                .map_or_else(|| String::from("-"), |v| format_size(v.data_added));
+            print!(
+                "{:width$}  {:12}  {:7}  {:12}  {}",
+                entry.job, last_run, result, next_run, added
+            );
+            if entry.stale {
+                print!("  (removed from config)");
+            }
+            println!();
+            if let Some(error) = entry.error.and_then(|v| v.lines().next()) {
+                println!("{}  error: {}", " ".repeat(width), error);
+            }
+            if let Some(error) = entry.check_error.and_then(|v| v.lines().next()) {
+                println!("{}  check error: {}", " ".repeat(width), error);
+            }
+            if let Some(error) = entry.prune_error.and_then(|v| v.lines().next()) {
+                println!("{}  prune error: {}", " ".repeat(width), error);
+            }
+            if let Some(error) = entry.copy_error.and_then(|v| v.lines().next()) {
+                println!("{}  copy error: {}", " ".repeat(width), error);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = std::env::temp_dir().join(format!("backuprs_state_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(STATE_FILE);
+        let mut state = State::load(&path).unwrap();
+        let last_run = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let summary: BackupSummary = serde_json::from_str(
+            r#"{"files_new":2,"files_changed":1,"files_unmodified":3,"dirs_new":0,"dirs_changed":1,"dirs_unmodified":4,"data_blobs":2,"tree_blobs":1,"data_added":1024,"total_files_processed":6,"total_bytes_processed":4096,"total_duration":1.5,"snapshot_id":"abcd1234"}"#,
+        )
+        .unwrap();
+        state.jobs.insert(
+            String::from("job"),
+            JobState {
+                last_run: Some(last_run),
+                success: Some(true),
+                summary: Some(summary),
+                check_error: Some(String::from("pack damaged")),
+                ..Default::default()
+            },
+        );
+        state.save().unwrap();
+        assert!(!path.with_extension("json.tmp").exists());
+
+        let loaded = State::load(&path).unwrap();
+        assert_eq!(loaded.path, path);
+        assert_eq!(loaded.last_run("job"), Some(last_run));
+        assert_eq!(loaded.last_run("other"), None);
+        let job = &loaded.jobs["job"];
+        assert_eq!(job.success, Some(true));
+        assert_eq!(job.summary.as_ref().unwrap().snapshot_id, "abcd1234");
+        assert_eq!(job.check_error.as_deref(), Some("pack damaged"));
+        assert_eq!(job.last_check, None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_missing_or_corrupt() {
+        let dir =
+            std::env::temp_dir().join(format!("backuprs_state_corrupt_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let global = Global {
+            scratch_dir: dir.clone(),
+            ..Default::default()
+        };
+        let path = dir.join(STATE_FILE);
+        // not existing yet, nothing to restore
+        let state = State::open(&global).unwrap();
+        assert!(state.jobs.is_empty());
+        assert_eq!(state.version, STATE_VERSION);
+
+        std::fs::write(&path, "{\"version\": 1, \"jobs\": {").unwrap();
+        assert!(State::open(&global).is_err());
+        // started empty, overwritten by the next save
+        let state = State::load_logged(&global);
+        assert!(state.jobs.is_empty());
+        assert_eq!(state.path, path);
+
+        std::fs::write(&path, "{\"version\": 99, \"jobs\": {}}").unwrap();
+        let err = State::open(&global).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Unsupported state file version 99"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}